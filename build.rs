@@ -0,0 +1,107 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const OPCODES_PATH: &str = "src/cpu/opcodes.json";
+
+/// Instructions whose "Z", "NZ", "C" and "NC" operands are branch conditions
+/// rather than registers
+const CONDITIONAL: [&str; 4] = ["JP", "JR", "CALL", "RET"];
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", OPCODES_PATH);
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let source = fs::read_to_string(OPCODES_PATH).expect("Couldn't read opcodes.json");
+    let opcodes = json::parse(&source).expect("Couldn't parse opcodes.json");
+
+    let mut out = String::new();
+    out.push_str("// Generated by build.rs from src/cpu/opcodes.json, do not edit.\n\n");
+    write_table(&mut out, "UNPREFIXED", &opcodes["unprefixed"]);
+    write_table(&mut out, "CB_PREFIXED", &opcodes["cbprefixed"]);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("opcode_table.rs"), out)
+        .expect("Couldn't write opcode table");
+}
+
+fn write_table(out: &mut String, name: &str, table: &json::JsonValue) {
+    writeln!(out, "pub static {}: [OpcodeInfo; 256] = [", name).unwrap();
+    for op in 0..=0xFFu32 {
+        let key = format!("0x{:02X}", op);
+        let entry = &table[key.as_str()];
+        assert!(!entry.is_null(), "Missing opcode {} in {}", key, name);
+
+        let mnemonic = entry["mnemonic"].as_str().unwrap();
+        let instruction = if mnemonic.starts_with("ILLEGAL") {
+            "ILLEGAL"
+        } else {
+            mnemonic
+        };
+
+        let operands = decode_operands(mnemonic, &entry["operands"]);
+        let bytes = entry["bytes"].as_u8().unwrap();
+
+        // Conditional instructions list the taken cycles first
+        let cycles = &entry["cycles"];
+        let taken = cycles[0].as_u8().unwrap();
+        let not_taken = if cycles.len() > 1 {
+            cycles[1].as_u8().unwrap()
+        } else {
+            taken
+        };
+
+        writeln!(
+            out,
+            "    // {} {}\n    OpcodeInfo {{ mnemonic: Instruction::{}, operands: [{}, {}], bytes: {}, cycles: [{}, {}] }},",
+            key, mnemonic, instruction, operands[0], operands[1], bytes, taken, not_taken
+        )
+        .unwrap();
+    }
+    out.push_str("];\n\n");
+}
+
+fn decode_operands(mnemonic: &str, operands: &json::JsonValue) -> [String; 2] {
+    let mut kinds: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < operands.len() {
+        let operand = &operands[i];
+        let name = operand["name"].as_str().unwrap();
+        let immediate = operand["immediate"].as_bool().unwrap();
+        let increment = operand["increment"].as_bool().unwrap_or(false);
+        let decrement = operand["decrement"].as_bool().unwrap_or(false);
+
+        let kind = match name {
+            // "SP+" followed by e8 is a single signed-offset operand (LD HL,SP+e8)
+            "SP" if increment && immediate => {
+                i += 1;
+                "OperandKind::SpOffset".to_string()
+            }
+            "n8" => "OperandKind::Imm8".to_string(),
+            "n16" => "OperandKind::Imm16".to_string(),
+            "e8" => "OperandKind::Offset8".to_string(),
+            "a8" => "OperandKind::Addr8".to_string(),
+            "a16" if immediate => "OperandKind::Imm16".to_string(),
+            "a16" => "OperandKind::Addr16".to_string(),
+            "Z" | "NZ" | "C" | "NC" if CONDITIONAL.contains(&mnemonic) => {
+                format!("OperandKind::Condition(FlagNames::{})", name)
+            }
+            _ if name.starts_with('$') => {
+                let vector = u8::from_str_radix(&name[1..], 16).unwrap();
+                format!("OperandKind::Vector(0x{:02X})", vector)
+            }
+            _ if name.parse::<u8>().is_ok() => format!("OperandKind::Bit({})", name),
+            _ if immediate => format!("OperandKind::Register(RegisterNames::{})", name),
+            _ if increment => format!("OperandKind::IndirectInc(RegisterNames::{})", name),
+            _ if decrement => format!("OperandKind::IndirectDec(RegisterNames::{})", name),
+            _ => format!("OperandKind::Indirect(RegisterNames::{})", name),
+        };
+        kinds.push(kind);
+        i += 1;
+    }
+
+    assert!(kinds.len() <= 2, "Too many operands for {}", mnemonic);
+    kinds.resize(2, "OperandKind::None".to_string());
+    [kinds[0].clone(), kinds[1].clone()]
+}
@@ -3,20 +3,22 @@ mod registers;
 mod shared;
 
 use crate::MMU;
-use json::{self, JsonValue};
 use log;
+pub use shared::EmulationError;
 use shared::{
-    get_opcodes, is_flag, match_string_preinstruction, match_string_to_flag,
-    match_string_to_instruction, match_string_to_register, Instruction, Operand, RegisterNames,
-    Registers,
+    ErrorReason, Instruction, OpcodeInfo, Operand, OperandKind, RegisterNames, Registers,
+    CB_PREFIXED, UNPREFIXED,
 };
 
 pub struct CPU<'a> {
     registers: Registers,
     pub memory: &'a mut MMU,
     halted: bool,
-    opcodes: json::JsonValue,
-    ime: bool, // Interrupt Master Enable flag
+    stopped: bool,     // Low-power mode entered by STOP, left on a joypad press
+    halt_bug: bool,    // Next opcode fetch doesn't increment PC
+    locked: bool,      // Hung by an illegal opcode
+    ime: bool,         // Interrupt Master Enable flag
+    ime_pending: bool, // EI takes effect after the following instruction
 }
 
 impl<'a> CPU<'a> {
@@ -26,115 +28,219 @@ impl<'a> CPU<'a> {
             registers: Registers::new(),
             memory: mmu,
             halted: false,
-            opcodes: get_opcodes(),
+            stopped: false,
+            halt_bug: false,
+            locked: false,
             ime: false,
+            ime_pending: false,
         }
     }
 
-    // read word from rom
+    // read word at PC, through the full memory map so code can run from RAM
     fn read_word(&mut self) -> u16 {
-        let low = self.memory.read_rom(self.registers.pc);
-        let high = self.memory.read_rom(self.registers.pc + 1);
-        self.registers.pc += 2;
+        let low = self.read_byte();
+        let high = self.read_byte();
         ((high as u16) << 8) | (low as u16)
     }
 
-    // read byte from rom
+    // read byte at PC
     fn read_byte(&mut self) -> u8 {
-        let byte = self.memory.read_rom(self.registers.pc);
-        self.registers.pc += 1;
+        let byte = self.memory.read(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         byte
     }
 
-    pub fn step(&mut self) {
+    /// Executes a single instruction and returns the number of T-cycles it took.
+    pub fn step(&mut self) -> Result<u32, EmulationError> {
+        if self.locked {
+            // Only a reset gets the CPU out of this state
+            return Ok(4);
+        }
+
+        if self.stopped {
+            if !self.memory.joypad_woke() {
+                return Ok(4);
+            }
+            self.stopped = false;
+        }
+
+        if let Some(cycles) = self.handle_interrupts() {
+            return Ok(cycles);
+        }
+
+        // An EI from the previous instruction enables interrupts from here on,
+        // so they are first serviced after the current instruction
+        if self.ime_pending {
+            self.ime_pending = false;
+            self.ime = true;
+        }
+
         if self.halted {
-            return;
+            // Any enabled interrupt ends HALT, even with IME cleared
+            if self.memory.pending_interrupt().is_none() {
+                // The clock keeps running while the CPU is halted
+                return Ok(4);
+            }
+            self.halted = false;
         }
 
         // Fetch the opcode from memory
+        let pc = self.registers.pc;
         let opcode = self.read_byte();
+        if self.halt_bug {
+            // The byte after HALT gets read twice
+            self.halt_bug = false;
+            self.registers.pc = self.registers.pc.wrapping_sub(1);
+        }
 
         // log::debug!("Opcode : {:02X}", opcode);
         // log::debug!("Program counter : {:02X}", self.registers.pc);
         // Execute the instruction
-        self.execute_instruction(opcode);
+        self.execute_instruction(opcode, pc)
+    }
+
+    /// Dispatches the highest priority pending interrupt if IME is set.
+    /// Returns the T-cycles spent, or None if no interrupt was serviced.
+    fn handle_interrupts(&mut self) -> Option<u32> {
+        if !self.ime {
+            return None;
+        }
+        let interrupt = self.memory.pending_interrupt()?;
+
+        // Waking up from HALT costs one more M-cycle
+        let cycles = if self.halted { 24 } else { 20 };
+        self.halted = false;
+        self.ime = false;
+        self.memory.acknowledge_interrupt(interrupt);
+
+        // Push PC and jump to the interrupt vector
+        self.registers.sp = self.registers.sp.wrapping_sub(2);
+        self.memory.write_word(self.registers.sp, self.registers.pc);
+        self.registers.pc = interrupt.vector();
+
+        log::debug!("Interrupt {:?} \n", interrupt);
+        Some(cycles)
     }
 
-    fn get_operand(&mut self, operand: &str, op_im: bool, instr: &Instruction) -> Operand {
-        if operand.contains("16") {
-            let value = self.read_word();
-            if *instr == Instruction::LD && !op_im {
+    fn halt(&mut self) {
+        if !self.ime && self.memory.pending_interrupt().is_some() {
+            // HALT bug: the CPU doesn't halt and fails to increment PC
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+
+    /// Enters low-power mode, or performs the CGB speed switch if one was
+    /// requested through KEY1. Returns the extra T-cycles spent.
+    fn stop(&mut self) -> u32 {
+        if self.memory.speed_switch_requested() {
+            self.memory.switch_speed();
+            // The CPU is paused for 2050 M-cycles while the clock settles
+            return 8200;
+        }
+        self.memory.enter_stop();
+        self.stopped = true;
+        0
+    }
+
+    fn get_operand(&mut self, kind: OperandKind) -> Result<Operand, ErrorReason> {
+        let operand = match kind {
+            OperandKind::None => Operand::NIL,
+            OperandKind::Register(reg) => Operand::Register(reg),
+            // LDH resolves (C) to 0xFF00 + C itself
+            OperandKind::Indirect(RegisterNames::C) => Operand::Register(RegisterNames::C),
+            OperandKind::Indirect(reg) => {
+                let value = self.registers.get_register_value_16(reg)?;
+                Operand::Memory(value)
+            }
+            // (HL+) and (HL-) use HL, then step it
+            OperandKind::IndirectInc(reg) => {
+                let value = self.registers.get_register_value_16(reg)?;
+                self.registers
+                    .set_register_value_16(reg, value.wrapping_add(1))?;
+                Operand::Memory(value)
+            }
+            OperandKind::IndirectDec(reg) => {
+                let value = self.registers.get_register_value_16(reg)?;
+                self.registers
+                    .set_register_value_16(reg, value.wrapping_sub(1))?;
                 Operand::Memory(value)
-            } else {
+            }
+            OperandKind::Offset8 => {
+                let offset = self.read_byte() as i8;
+                Operand::Offset(offset)
+            }
+            OperandKind::Imm8 | OperandKind::Addr8 => {
+                // get the immediate value
+                let imm = self.read_byte();
+                Operand::Immediate(imm)
+            }
+            OperandKind::Imm16 => {
+                let value = self.read_word();
                 Operand::Immediate16(value)
             }
-        } else if operand.contains("8") {
-            // get the immediate value
-            let imm = self.read_byte();
-            Operand::Immediate(imm)
-        } else if is_flag(operand, instr) {
-            Operand::Flag(match_string_to_flag(operand))
-        } else {
-            // get the immediate value
-            let op_reg = match_string_to_register(operand);
-            if op_im {
-                Operand::Register(op_reg)
-            } else {
-                let value = self.registers.get_register_value_16(op_reg);
+            OperandKind::Addr16 => {
+                let value = self.read_word();
                 Operand::Memory(value)
             }
-        }
+            OperandKind::SpOffset => {
+                let offset = self.read_byte() as i8;
+                Operand::SpOffset(offset)
+            }
+            OperandKind::Condition(flag) => Operand::Flag(flag),
+            OperandKind::Vector(vector) => Operand::Immediate16(vector as u16),
+            OperandKind::Bit(bit) => Operand::Immediate(bit),
+        };
+        Ok(operand)
     }
 
-    fn execute_instruction(&mut self, op: u8) {
-        // Check if it's a prefixed instruction (0xCB)
-        if op == 0xCB {
-            self.execute_prefixed_instruction();
-            return;
-        }
+    fn get_operands(&mut self, info: &OpcodeInfo) -> Result<[Operand; 2], ErrorReason> {
+        let operand1 = self.get_operand(info.operands[0])?;
+        let operand2 = self.get_operand(info.operands[1])?;
+        Ok([operand1, operand2])
+    }
 
-        // take the hex string of op
-        let op: String = format!("0x{:02X}", op);
-        // print OPCODE
-        let a: JsonValue = self.opcodes["unprefixed"][op].clone();
+    /// Number of T-cycles for an instruction, picking the not-taken figure
+    /// when its branch condition is false
+    fn get_cycles(&self, info: &OpcodeInfo, ops: &[Operand; 2]) -> u32 {
+        let taken = match ops[0] {
+            Operand::Flag(flag) => self.registers.get_flag(&flag),
+            _ => true,
+        };
+        if taken {
+            info.cycles[0] as u32
+        } else {
+            info.cycles[1] as u32
+        }
+    }
 
-        // get the instruction from the opcode
-        let instr: Instruction = match_string_to_instruction((&a["mnemonic"]).as_str().unwrap());
+    fn execute_instruction(&mut self, op: u8, pc: u16) -> Result<u32, EmulationError> {
+        let info = &UNPREFIXED[op as usize];
+        let instr = info.mnemonic;
 
         // Check for PREFIX instruction (0xCB)
         if instr == Instruction::PREFIX {
-            self.execute_prefixed_instruction();
-            return;
+            return self.execute_prefixed_instruction(pc);
         }
 
-        let operands: &JsonValue = &a["operands"];
-        let mut ops: [Operand; 2] = [Operand::NIL; 2];
-        if operands.len() == 2 {
-            // get the first operand
-            let op1 = &operands[0]["name"].as_str().unwrap();
-            let op1_imm: bool = operands[0]["immediate"].as_bool().unwrap();
-            // get the second operand
-            let op2 = &operands[1]["name"].as_str().unwrap();
-            // get the immediate value
-            let op2_imm: bool = operands[1]["immediate"].as_bool().unwrap();
-
-            // if op1_imm is true, then it is an immediate value
-            // let operand1 = Operand::Register(match_string_to_register(op1));
-            let operand1 = self.get_operand(op1, op1_imm, &instr);
-            // if op2_imm is true, then it is an immediate value
-            let operand2 = self.get_operand(op2, op2_imm, &instr);
-            ops[0] = operand1;
-            ops[1] = operand2;
-        } else if operands.len() == 1 {
-            // get the first operand
-            let op1 = &operands[0]["name"].as_str().unwrap();
-            let op1_imm: bool = operands[0]["immediate"].as_bool().unwrap();
-            // get instruction
-            let instr = match_string_to_instruction((&a["mnemonic"]).as_str().unwrap());
-
-            let operand1 = self.get_operand(op1, op1_imm, &instr);
-            ops[0] = operand1;
-        }
+        let operands_start = self.registers.pc;
+        let ops = self
+            .get_operands(info)
+            .map_err(|reason| EmulationError::Unprefixed {
+                pc,
+                opcode: op,
+                reason,
+            })?;
+        // The table's length includes the opcode itself
+        debug_assert_eq!(
+            self.registers.pc.wrapping_sub(operands_start),
+            info.bytes as u16 - 1,
+            "operand length of opcode 0x{:02X}",
+            op
+        );
+        // Conditions have to be checked before the branch is executed
+        let cycles = self.get_cycles(info, &ops);
 
         // For debugging
         match ops[1] {
@@ -147,133 +253,440 @@ impl<'a> CPU<'a> {
             }
         }
 
-        instr.match_instruction(&mut self.registers, &mut self.memory, &ops);
+        let result = match instr {
+            // Instructions that change the interrupt state of the CPU
+            Instruction::DI => {
+                self.ime = false;
+                self.ime_pending = false;
+                Ok(())
+            }
+            Instruction::EI => {
+                self.ime_pending = true;
+                Ok(())
+            }
+            Instruction::RETI => {
+                self.ime = true;
+                Instruction::RET.match_instruction(&mut self.registers, self.memory, &ops)
+            }
+            Instruction::HALT => {
+                self.halt();
+                Ok(())
+            }
+            Instruction::STOP => return Ok(cycles + self.stop()),
+            Instruction::ILLEGAL => {
+                // Unused opcodes hang the CPU on real hardware
+                log::warn!("Illegal opcode 0x{:02X} at 0x{:04X}, CPU locked up", op, pc);
+                self.locked = true;
+                Ok(())
+            }
+            _ => instr.match_instruction(&mut self.registers, self.memory, &ops),
+        };
+        result.map_err(|reason| EmulationError::Unprefixed {
+            pc,
+            opcode: op,
+            reason,
+        })?;
+        Ok(cycles)
     }
 
-    fn execute_prefixed_instruction(&mut self) {
+    fn execute_prefixed_instruction(&mut self, pc: u16) -> Result<u32, EmulationError> {
         // Read the second byte of the prefixed instruction
         let op = self.read_byte();
-        let op_str = format!("0x{:02X}", op);
-
-        // Get the instruction from the prefixed opcodes
-        let a: JsonValue = self.opcodes["cbprefixed"][op_str].clone();
-        let instr_str = (&a["mnemonic"]).as_str().unwrap();
-
-        // Match the CB prefixed instruction
-        let instr = match_string_preinstruction(instr_str);
-
-        // Process operands
-        let operands: &JsonValue = &a["operands"];
-        let mut ops: [Operand; 2] = [Operand::NIL; 2];
-
-        if operands.len() == 2 {
-            let op1 = &operands[0]["name"].as_str().unwrap();
-            let op1_imm: bool = operands[0]["immediate"].as_bool().unwrap();
-            let op2 = &operands[1]["name"].as_str().unwrap();
-            let op2_imm: bool = operands[1]["immediate"].as_bool().unwrap();
-
-            let operand1 = if op1.contains("bit") {
-                // For BIT, SET, RES instructions, first operand is bit number
-                let bit_num = op1[3..].parse::<u8>().unwrap();
-                Operand::Immediate(bit_num)
-            } else {
-                self.get_operand(op1, op1_imm, &instr)
-            };
-
-            let operand2 = self.get_operand(op2, op2_imm, &instr);
-            ops[0] = operand1;
-            ops[1] = operand2;
-        } else if operands.len() == 1 {
-            let op1 = &operands[0]["name"].as_str().unwrap();
-            let op1_imm: bool = operands[0]["immediate"].as_bool().unwrap();
-
-            let operand1 = self.get_operand(op1, op1_imm, &instr);
-            ops[0] = operand1;
-        }
+        let info = &CB_PREFIXED[op as usize];
+        let instr = info.mnemonic;
+
+        let ops = self
+            .get_operands(info)
+            .map_err(|reason| EmulationError::Prefixed {
+                pc,
+                opcode: op,
+                reason,
+            })?;
 
         log::debug!("CB Prefixed: {:?} {:?},{:?}\n", instr, ops[0], ops[1]);
 
-        // Execute the CB prefixed instruction
-        // match instr {
-        //     Instruction::RLC => self.execute_rlc(&ops),
-        //     Instruction::RRC => self.execute_rrc(&ops),
-        //     Instruction::RL => self.execute_rl(&ops),
-        //     Instruction::RR => self.execute_rr(&ops),
-        //     Instruction::SLA => self.execute_sla(&ops),
-        //     Instruction::SRA => self.execute_sra(&ops),
-        //     Instruction::SWAP => self.execute_swap(&ops),
-        //     Instruction::SRL => self.execute_srl(&ops),
-        //     Instruction::BIT => self.execute_bit(&ops),
-        //     Instruction::RES => self.execute_res(&ops),
-        //     Instruction::SET => self.execute_set(&ops),
-        //     _ => panic!("Unhandled CB-prefixed instruction: {:?}", instr),
-        // }
-        //
-        instr.match_prefix_instruction(&mut self.registers, &mut self.memory, &ops);
+        instr
+            .match_prefix_instruction(&mut self.registers, self.memory, &ops)
+            .map_err(|reason| EmulationError::Prefixed {
+                pc,
+                opcode: op,
+                reason,
+            })?;
+
+        // The CB table's cycle counts already include fetching the prefix
+        Ok(info.cycles[0] as u32)
     }
 
     pub fn print_registers(&self) {
+        use RegisterNames::*;
         println!("Register values:");
+        for register in [A, B, C, D, E, H, L] {
+            if let Ok(value) = self.registers.get_register_value_8(register) {
+                println!("{:<3} 0x{:02X}", format!("{:?}:", register), value);
+            }
+        }
+        for register in [AF, BC, DE, HL] {
+            if let Ok(value) = self.registers.get_register_value_16(register) {
+                println!("{:<3} 0x{:04X}", format!("{:?}:", register), value);
+            }
+        }
+        println!("SP: 0x{:04X}", self.registers.sp);
+        println!("PC: 0x{:04X}", self.registers.pc);
         println!(
-            "A:  0x{:02X}",
-            self.registers.get_register_value_8(RegisterNames::A)
+            "Flags: Z={}, N={}, H={}, C={}",
+            self.registers.flag.get_z(),
+            self.registers.flag.get_n(),
+            self.registers.flag.get_h(),
+            self.registers.flag.get_c()
         );
+    }
+}
 
-        println!(
-            "B:  0x{:02X}",
-            self.registers.get_register_value_8(RegisterNames::B)
-        );
-        println!(
-            "C:  0x{:02X}",
-            self.registers.get_register_value_8(RegisterNames::C)
-        );
-        println!(
-            "D:  0x{:02X}",
-            self.registers.get_register_value_8(RegisterNames::D)
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::{Button, Interrupt};
+
+    /// Builds a 32 KiB ROM with the given program at the entry point (0x100)
+    fn rom_with_program(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        // JR NZ,+0 ; JR Z,+0
+        let mut mmu = MMU::new(rom_with_program(&[0x20, 0x00, 0x28, 0x00]));
+        let mut cpu = CPU::new(&mut mmu);
+        cpu.registers.flag.z = true;
+
+        assert_eq!(cpu.step().unwrap(), 8);
+        assert_eq!(cpu.step().unwrap(), 12);
+        assert_eq!(cpu.registers.pc, 0x104);
+    }
+
+    #[test]
+    fn test_ei_delay_and_dispatch() {
+        // EI ; NOP ; NOP
+        let mut mmu = MMU::new(rom_with_program(&[0xFB, 0x00, 0x00]));
+        mmu.write(0xFFFF, Interrupt::Timer.bit());
+        mmu.request_interrupt(Interrupt::Timer);
+        let mut cpu = CPU::new(&mut mmu);
+
+        assert_eq!(cpu.step().unwrap(), 4);
+        // The instruction after EI still runs before the interrupt
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.registers.pc, 0x102);
+
+        assert_eq!(cpu.step().unwrap(), 20);
+        assert_eq!(cpu.registers.pc, 0x50);
+        assert_eq!(cpu.memory.read_word(cpu.registers.sp), 0x102);
+        assert_eq!(cpu.memory.read(0xFF0F), 0xE0);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_ime() {
+        // HALT ; INC A
+        let mut mmu = MMU::new(rom_with_program(&[0x76, 0x3C]));
+        mmu.write(0xFFFF, Interrupt::VBlank.bit());
+        let mut cpu = CPU::new(&mut mmu);
+
+        cpu.step().unwrap();
+        assert!(cpu.halted);
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.registers.pc, 0x101);
+
+        // The interrupt isn't serviced, execution just resumes
+        cpu.memory.request_interrupt(Interrupt::VBlank);
+        cpu.step().unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.pc, 0x102);
+        assert_eq!(
+            cpu.registers
+                .get_register_value_8(RegisterNames::A)
+                .unwrap(),
+            0x02
         );
-        println!(
-            "E:  0x{:02X}",
-            self.registers.get_register_value_8(RegisterNames::E)
+    }
+
+    #[test]
+    fn test_halt_bug() {
+        // HALT ; INC A
+        let mut mmu = MMU::new(rom_with_program(&[0x76, 0x3C]));
+        mmu.write(0xFFFF, Interrupt::Timer.bit());
+        mmu.request_interrupt(Interrupt::Timer);
+        let mut cpu = CPU::new(&mut mmu);
+
+        cpu.step().unwrap();
+        assert!(!cpu.halted);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x101);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x102);
+        assert_eq!(
+            cpu.registers
+                .get_register_value_8(RegisterNames::A)
+                .unwrap(),
+            0x03
         );
-        println!(
-            "H:  0x{:02X}",
-            self.registers.get_register_value_8(RegisterNames::H)
+    }
+
+    #[test]
+    fn test_stop_waits_for_joypad() {
+        // STOP ; NOP
+        let mut mmu = MMU::new(rom_with_program(&[0x10, 0x00, 0x00]));
+        // Select the directions, and leave a stale joypad interrupt in IF
+        mmu.write(0xFF00, 0x20);
+        mmu.request_interrupt(Interrupt::Joypad);
+        mmu.tick(0x400);
+        assert_ne!(mmu.read(0xFF04), 0);
+        let mut cpu = CPU::new(&mut mmu);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory.read(0xFF04), 0);
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.registers.pc, 0x102);
+
+        // Only a line going low ends STOP
+        cpu.memory.press(Button::Right);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x103);
+    }
+
+    #[test]
+    fn test_speed_switch() {
+        // LD A,0x01 ; LDH (0x4D),A ; STOP ; NOP
+        let mut rom = rom_with_program(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x00]);
+        rom[0x143] = 0x80;
+        let mut mmu = MMU::new(rom);
+        let mut cpu = CPU::new(&mut mmu);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory.read(0xFF4D), 0x7F);
+
+        // STOP switches speed instead of stopping
+        let cycles = UNPREFIXED[0x10].cycles[0] as u32;
+        assert_eq!(cpu.step().unwrap(), cycles + 8200);
+        assert_eq!(cpu.memory.read(0xFF4D), 0xFE);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x107);
+    }
+
+    #[test]
+    fn test_sp_offset_flags() {
+        // LD SP,0xFFF8 ; LD HL,SP+0x08 ; ADD SP,1
+        let mut mmu = MMU::new(rom_with_program(&[
+            0x31, 0xF8, 0xFF, 0xF8, 0x08, 0xE8, 0x01,
+        ]));
+        let mut cpu = CPU::new(&mut mmu);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.registers
+                .get_register_value_16(RegisterNames::HL)
+                .unwrap(),
+            0x0000
         );
-        println!(
-            "L:  0x{:02X}",
-            self.registers.get_register_value_8(RegisterNames::L)
+        assert_eq!(cpu.registers.get_flags(), (false, false, true, true));
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.sp, 0xFFF9);
+        assert_eq!(cpu.registers.get_flags(), (false, false, false, false));
+    }
+
+    #[test]
+    fn test_hl_increment_and_sp_store() {
+        // LD HL,0xC000 ; LD (HL+),A ; LD A,(HL-) ; LD (0xC010),SP
+        let mut mmu = MMU::new(rom_with_program(&[
+            0x21, 0x00, 0xC0, 0x22, 0x3A, 0x08, 0x10, 0xC0,
+        ]));
+        mmu.write(0xC001, 0x42);
+        let mut cpu = CPU::new(&mut mmu);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory.read(0xC000), 0x01);
+        assert_eq!(
+            cpu.registers
+                .get_register_value_16(RegisterNames::HL)
+                .unwrap(),
+            0xC001
         );
-        println!(
-            "AF: 0x{:04X}",
-            self.registers.get_register_value_16(RegisterNames::AF)
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.registers
+                .get_register_value_8(RegisterNames::A)
+                .unwrap(),
+            0x42
         );
-        println!(
-            "BC: 0x{:04X}",
-            self.registers.get_register_value_16(RegisterNames::BC)
+        assert_eq!(
+            cpu.registers
+                .get_register_value_16(RegisterNames::HL)
+                .unwrap(),
+            0xC000
         );
-        println!(
-            "DE: 0x{:04X}",
-            self.registers.get_register_value_16(RegisterNames::DE)
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory.read_word(0xC010), 0xFFFE);
+    }
+
+    #[test]
+    fn test_rst_and_reti() {
+        // RST 0x08 at 0x100, RETI at 0x08
+        let mut rom = rom_with_program(&[0xCF]);
+        rom[0x08] = 0xD9;
+        let mut mmu = MMU::new(rom);
+        let mut cpu = CPU::new(&mut mmu);
+
+        assert_eq!(cpu.step().unwrap(), 16);
+        assert_eq!(cpu.registers.pc, 0x08);
+        assert_eq!(cpu.step().unwrap(), 16);
+        assert_eq!(cpu.registers.pc, 0x101);
+        assert!(cpu.ime);
+    }
+
+    #[test]
+    fn test_daa_and_push_af() {
+        // LD A,0x45 ; ADD A,0x38 ; DAA ; PUSH AF ; POP BC
+        let mut mmu = MMU::new(rom_with_program(&[
+            0x3E, 0x45, 0xC6, 0x38, 0x27, 0xF5, 0xC1,
+        ]));
+        let mut cpu = CPU::new(&mut mmu);
+
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(
+            cpu.registers
+                .get_register_value_16(RegisterNames::BC)
+                .unwrap(),
+            0x8300
         );
-        println!(
-            "HL: 0x{:04X}",
-            self.registers.get_register_value_16(RegisterNames::HL)
+    }
+
+    #[test]
+    fn test_stack_wraps_around() {
+        // POP BC with SP=0xFFFF reads IE, then wraps to 0x0000
+        let mut rom = rom_with_program(&[0xC1]);
+        rom[0] = 0x12;
+        let mut mmu = MMU::new(rom);
+        mmu.write(0xFFFF, 0x05);
+        let mut cpu = CPU::new(&mut mmu);
+        cpu.registers.sp = 0xFFFF;
+
+        cpu.step().unwrap();
+        let low = cpu.memory.read(0xFFFF);
+        assert_eq!(
+            cpu.registers
+                .get_register_value_16(RegisterNames::BC)
+                .unwrap(),
+            0x1200 | low as u16
         );
-        println!("SP: 0x{:04X}", self.registers.sp);
-        println!("PC: 0x{:04X}", self.registers.pc);
-        println!(
-            "Flags: Z={}, N={}, H={}, C={}",
-            self.registers.flag.get_z(),
-            self.registers.flag.get_n(),
-            self.registers.flag.get_h(),
-            self.registers.flag.get_c()
+        assert_eq!(cpu.registers.sp, 0x0001);
+    }
+
+    #[test]
+    fn test_invalid_register() {
+        // 16-bit registers can't be used as 8-bit ones and vice versa
+        let mut registers = Registers::new();
+        let invalid = ErrorReason::InvalidOperand(Operand::Register(RegisterNames::HL));
+        let value = registers.get_register_value_8(RegisterNames::HL);
+        assert_eq!(value.unwrap_err(), invalid);
+        let result = registers.set_register_value_8(RegisterNames::HL, 0);
+        assert_eq!(result.unwrap_err(), invalid);
+
+        let invalid = ErrorReason::InvalidOperand(Operand::Register(RegisterNames::A));
+        let value = registers.get_register_value_16(RegisterNames::A);
+        assert_eq!(value.unwrap_err(), invalid);
+        let result = registers.set_register_value_16(RegisterNames::A, 0);
+        assert_eq!(result.unwrap_err(), invalid);
+    }
+
+    #[test]
+    fn test_operand_lengths() {
+        // Decoding reads as many bytes as the opcode table says
+        for (op, info) in UNPREFIXED.iter().enumerate() {
+            if info.mnemonic == Instruction::PREFIX {
+                continue;
+            }
+            let mut mmu = MMU::new(rom_with_program(&[op as u8]));
+            let mut cpu = CPU::new(&mut mmu);
+            cpu.read_byte();
+            cpu.get_operands(info).unwrap();
+            assert_eq!(cpu.registers.pc, 0x100 + info.bytes as u16, "0x{:02X}", op);
+        }
+    }
+
+    #[test]
+    fn test_execute_from_hram() {
+        // JP 0xFF80, with LD A,0x42 ; JP 0xC000 copied to HRAM
+        let mut mmu = MMU::new(rom_with_program(&[0xC3, 0x80, 0xFF]));
+        for (i, byte) in [0x3E, 0x42, 0xC3, 0x00, 0xC0].into_iter().enumerate() {
+            mmu.write(0xFF80 + i as u16, byte);
+        }
+        let mut cpu = CPU::new(&mut mmu);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.registers
+                .get_register_value_8(RegisterNames::A)
+                .unwrap(),
+            0x42
         );
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0xC000);
     }
-}
 
-// tests
+    #[test]
+    fn test_illegal_opcode_locks_up() {
+        // 0xD3 ; INC A
+        let mut mmu = MMU::new(rom_with_program(&[0xD3, 0x3C]));
+        mmu.write(0xFFFF, Interrupt::VBlank.bit());
+        let mut cpu = CPU::new(&mut mmu);
+
+        assert_eq!(cpu.step(), Ok(4));
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-// }
+        // Not even an interrupt wakes the CPU up
+        cpu.ime = true;
+        cpu.memory.request_interrupt(Interrupt::VBlank);
+        for _ in 0..4 {
+            assert_eq!(cpu.step(), Ok(4));
+        }
+        assert_eq!(cpu.registers.pc, 0x101);
+    }
+
+    #[test]
+    fn test_unhandled_instruction_error() {
+        let mut mmu = MMU::new(rom_with_program(&[]));
+        let mut cpu = CPU::new(&mut mmu);
+
+        let ops = [Operand::Register(RegisterNames::HL), Operand::NIL];
+        let result =
+            Instruction::INC.match_prefix_instruction(&mut cpu.registers, cpu.memory, &ops);
+        assert_eq!(
+            result,
+            Err(ErrorReason::UnhandledInstruction(Instruction::INC))
+        );
+    }
+
+    #[test]
+    fn test_prefixed_cycles() {
+        // LD HL,0xC000 ; BIT 0,(HL) ; SET 0,(HL) ; SET 0,B
+        let mut mmu = MMU::new(rom_with_program(&[
+            0x21, 0x00, 0xC0, 0xCB, 0x46, 0xCB, 0xC6, 0xCB, 0xC0,
+        ]));
+        let mut cpu = CPU::new(&mut mmu);
+
+        assert_eq!(cpu.step().unwrap(), 12);
+        assert_eq!(cpu.step().unwrap(), 12);
+        assert_eq!(cpu.step().unwrap(), 16);
+        assert_eq!(cpu.step().unwrap(), 8);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Instruction, Operand, RegisterNames, Registers};
+use super::{ErrorReason, Instruction, Operand, RegisterNames, Registers};
 use crate::MMU;
 
 /// Represents an operand, which can be a register or a memory address.
@@ -11,17 +11,18 @@ impl Instruction {
         operand2: Operand,
         operation: F,
         operation2: T,
-    ) where
-        F: Fn(&mut Registers, &mut MMU, Operand, Operand),
-        T: Fn(&mut Registers, Operand, Operand, &mut MMU),
+    ) -> Result<(), ErrorReason>
+    where
+        F: Fn(&mut Registers, &mut MMU, Operand, Operand) -> Result<(), ErrorReason>,
+        T: Fn(&mut Registers, Operand, Operand, &mut MMU) -> Result<(), ErrorReason>,
     {
         let blen = operand1.get_bit_length();
         if blen == 16 {
             operation2(registers, operand1, operand2, memory)
         } else if blen == 8 {
-            operation(registers, memory, operand1, operand2);
+            operation(registers, memory, operand1, operand2)
         } else {
-            panic!("Invalid bit length");
+            Err(ErrorReason::InvalidOperand(operand1))
         }
     }
     pub fn match_instruction(
@@ -29,7 +30,7 @@ impl Instruction {
         registers: &mut Registers,
         memory: &mut MMU,
         ops: &[Operand; 2],
-    ) {
+    ) -> Result<(), ErrorReason> {
         let operand1 = ops[0];
         let operand2 = ops[1];
         match self {
@@ -38,73 +39,87 @@ impl Instruction {
                 // do nothing
             }
             // 2 operand operation
-            Instruction::ADD => {
-                // ADD instruction
-                self.execute_two_operand(
-                    registers, memory, operand1, operand2, add_8bit, add_16bit,
-                );
-            }
+            Instruction::ADD => match operand2 {
+                // ADD SP,e8
+                Operand::Offset(offset) => add_sp_offset(registers, offset),
+                _ => {
+                    // ADD instruction
+                    self.execute_two_operand(
+                        registers, memory, operand1, operand2, add_8bit, add_16bit,
+                    )?;
+                }
+            },
             Instruction::ADC => {
                 // ADC instruction
-                adc_8bit(registers, memory, operand1, operand2);
+                adc_8bit(registers, memory, operand1, operand2)?;
             }
             Instruction::SUB => {
                 // SUB instruction
-                sub_8bit(registers, memory, operand1, operand2);
+                sub_8bit(registers, memory, operand1, operand2)?;
             }
-            Instruction::LD => {
-                ld(registers, operand1, operand2, memory);
+            Instruction::SBC => {
+                // SBC instruction
+                sbc_8bit(registers, memory, operand1, operand2)?;
             }
+            Instruction::LD => match operand2 {
+                // LD HL,SP+e8
+                Operand::SpOffset(offset) => ld_hl_sp_offset(registers, offset)?,
+                _ => ld(registers, operand1, operand2, memory)?,
+            },
             Instruction::LDH => {
                 // LDH instruction - Load from or store to high memory area (0xFF00-0xFFFF)
-                ldh(registers, operand1, operand2, memory);
+                ldh(registers, operand1, operand2, memory)?;
             }
             Instruction::AND => {
                 // AND instruction
-                and_8bit(registers, memory, operand1, operand2);
+                and_8bit(registers, memory, operand1, operand2)?;
             }
             Instruction::OR => {
                 // OR instruction
-                or_8bit(registers, memory, operand1, operand2);
+                or_8bit(registers, memory, operand1, operand2)?;
             }
             Instruction::XOR => {
                 // XOR instruction
-                xor_8bit(registers, memory, operand1, operand2);
+                xor_8bit(registers, memory, operand1, operand2)?;
             }
             Instruction::CP => {
                 // CP instruction
-                cp_8bit(registers, memory, operand1, operand2);
+                cp_8bit(registers, memory, operand1, operand2)?;
             }
 
             // One or two operand
             Instruction::CALL => match operand2 {
-                Operand::NIL => call(registers, memory, operand1, true),
+                Operand::NIL => call(registers, memory, operand1, true)?,
                 _ => {
-                    let condition = operand1.read(&registers, &memory) == 1;
-                    call(registers, memory, operand2, condition);
+                    let condition = operand1.read(&registers, &memory)? == 1;
+                    call(registers, memory, operand2, condition)?;
                 }
             },
             Instruction::JP => match operand2 {
-                Operand::NIL => jp(registers, memory, operand1, true),
+                Operand::NIL => jp(registers, memory, operand1, true)?,
                 _ => {
-                    let condition = operand1.read(&registers, &memory) == 1;
-                    jp(registers, memory, operand2, condition);
+                    let condition = operand1.read(&registers, &memory)? == 1;
+                    jp(registers, memory, operand2, condition)?;
                 }
             },
             Instruction::JR => match operand2 {
-                Operand::NIL => jr(registers, memory, operand1, true),
+                Operand::NIL => jr(registers, memory, operand1, true)?,
                 _ => {
-                    let condition = operand1.read(&registers, &memory) == 1;
-                    jr(registers, memory, operand2, condition);
+                    let condition = operand1.read(&registers, &memory)? == 1;
+                    jr(registers, memory, operand2, condition)?;
                 }
             },
             Instruction::RET => match operand1 {
                 Operand::NIL => ret(registers, memory, true),
                 _ => {
-                    let condition = operand1.read(registers, memory) == 1;
+                    let condition = operand1.read(registers, memory)? == 1;
                     ret(registers, memory, condition);
                 }
             },
+            Instruction::RST => {
+                // RST instruction - call to a fixed vector
+                call(registers, memory, operand1, true)?;
+            }
 
             // One operand
             Instruction::INC => {
@@ -113,63 +128,68 @@ impl Instruction {
 
                 if bit_len == 8 {
                     // 8 bit inc
-                    inc_8bit(registers, memory, operand1);
+                    inc_8bit(registers, memory, operand1)?;
                 } else if bit_len == 16 {
                     // 16 bit inc
-                    inc_16bit(registers, operand1, memory);
+                    inc_16bit(registers, operand1, memory)?;
                 } else {
-                    panic!("Invalid operand size");
+                    return Err(ErrorReason::InvalidOperand(operand1));
                 }
             }
             Instruction::DEC => {
                 // DEC instruction
                 if operand1.get_bit_length() == 8 {
                     // 8 bit dec
-                    dec_8bit(registers, memory, operand1);
+                    dec_8bit(registers, memory, operand1)?;
                 } else if operand1.get_bit_length() == 16 {
                     // 16 bit dec
-                    dec_16bit(registers, operand1, memory);
+                    dec_16bit(registers, operand1, memory)?;
                 } else {
-                    panic!("Invalid operand size");
+                    return Err(ErrorReason::InvalidOperand(operand1));
                 }
             }
             Instruction::PUSH => {
                 // PUSH instruction
-                // self.registers.push(operand1);
-                push(registers, memory, operand1);
+                // self.registers.push(operand1)?;
+                push(registers, memory, operand1)?;
             }
 
             Instruction::POP => {
                 // POP instruction
-                // self.registers.pop(operand1);
-                // self.pop(operand1);
-                pop(registers, memory, operand1);
+                // self.registers.pop(operand1)?;
+                // self.pop(operand1)?;
+                pop(registers, memory, operand1)?;
             }
 
             // No operand
+            // Unlike their CB-prefixed versions, these always clear Z
             Instruction::RRCA => {
                 // RRCA instruction
-                rrc(registers, memory, Operand::Register(RegisterNames::A));
+                rrc(registers, memory, Operand::Register(RegisterNames::A))?;
+                registers.flag.z = false;
             }
             Instruction::RLCA => {
                 // RLCA instruction
-                rlc(registers, memory, Operand::Register(RegisterNames::A));
+                rlc(registers, memory, Operand::Register(RegisterNames::A))?;
+                registers.flag.z = false;
             }
             Instruction::RRA => {
                 // RRA instruction
-                rr(registers, memory, Operand::Register(RegisterNames::A));
+                rr(registers, memory, Operand::Register(RegisterNames::A))?;
+                registers.flag.z = false;
             }
             Instruction::RLA => {
                 // RLA instruction
-                rl(registers, memory, Operand::Register(RegisterNames::A));
+                rl(registers, memory, Operand::Register(RegisterNames::A))?;
+                registers.flag.z = false;
             }
             Instruction::CPL => {
                 // CPL instruction
-                cpl(registers);
+                cpl(registers)?;
             }
             Instruction::DAA => {
                 // DAA instruction
-                daa(registers);
+                daa(registers)?;
             }
 
             Instruction::SCF => {
@@ -180,14 +200,11 @@ impl Instruction {
                 // CCF instruction
                 ccf(registers);
             }
-            Instruction::DI => {
-                // DI instruction - Disable Interrupts
-                di(registers);
-            }
             _ => {
-                panic!("Unknown instruction: {:?}", self);
+                return Err(ErrorReason::UnhandledInstruction(*self));
             }
         }
+        Ok(())
     }
 
     pub fn match_prefix_instruction(
@@ -195,84 +212,106 @@ impl Instruction {
         registers: &mut Registers,
         memory: &mut MMU,
         ops: &[Operand; 2],
-    ) {
+    ) -> Result<(), ErrorReason> {
         let operand1 = ops[0];
         let operand2 = ops[1];
         match self {
             Instruction::RLC => {
-                rlc(registers, memory, operand1);
+                rlc(registers, memory, operand1)?;
             }
             Instruction::RRC => {
-                rrc(registers, memory, operand1);
+                rrc(registers, memory, operand1)?;
             }
             Instruction::RL => {
-                rl(registers, memory, operand1);
+                rl(registers, memory, operand1)?;
             }
             Instruction::RR => {
-                rr(registers, memory, operand1);
+                rr(registers, memory, operand1)?;
             }
             Instruction::SLA => {
-                sla(registers, memory, operand1);
+                sla(registers, memory, operand1)?;
             }
             Instruction::SRA => {
-                sra(registers, memory, operand1);
+                sra(registers, memory, operand1)?;
             }
             Instruction::SWAP => {
-                swap(registers, memory, operand1);
+                swap(registers, memory, operand1)?;
             }
             Instruction::SRL => {
-                srl(registers, memory, operand1);
+                srl(registers, memory, operand1)?;
             }
             Instruction::BIT => {
-                let bit_position = operand1.read(registers, memory);
-                bit(registers, memory, operand2, bit_position);
+                let bit_position = operand1.read(registers, memory)?;
+                bit(registers, memory, operand2, bit_position)?;
             }
             Instruction::RES => {
-                let bit_position = operand1.read(registers, memory);
-                res(registers, memory, operand2, bit_position);
+                let bit_position = operand1.read(registers, memory)?;
+                res(registers, memory, operand2, bit_position)?;
             }
             Instruction::SET => {
-                let bit_position = operand1.read(registers, memory);
-                set(registers, memory, operand2, bit_position);
+                let bit_position = operand1.read(registers, memory)?;
+                set(registers, memory, operand2, bit_position)?;
             }
-            _ => panic!("Unhandled CB-prefixed instruction: {:?}", self),
+            _ => return Err(ErrorReason::UnhandledInstruction(*self)),
         }
+        Ok(())
     }
 }
 
 // get operand bit length
 impl Operand {
-    pub fn read(&self, registers: &Registers, memory: &MMU) -> u8 {
+    pub fn read(&self, registers: &Registers, memory: &MMU) -> Result<u8, ErrorReason> {
         match self {
-            Operand::Register(reg) => registers.get_register_value_8(*reg),
-            Operand::Memory(addr) => memory.read(*addr),
-            Operand::Immediate(value) => *value,
-            Operand::Flag(flag) => registers.get_flag(flag) as u8,
-            _ => panic!("Invalid operand for read"),
+            Operand::Register(reg) if self.get_bit_length() == 8 => {
+                registers.get_register_value_8(*reg)
+            }
+            Operand::Memory(addr) => Ok(memory.read(*addr)),
+            Operand::Immediate(value) => Ok(*value),
+            Operand::Offset(value) => Ok(*value as u8),
+            Operand::Flag(flag) => Ok(registers.get_flag(flag) as u8),
+            _ => Err(ErrorReason::InvalidOperand(*self)),
         }
     }
 
-    pub fn write(&self, value: u8, registers: &mut Registers, memory: &mut MMU) {
+    pub fn write(
+        &self,
+        value: u8,
+        registers: &mut Registers,
+        memory: &mut MMU,
+    ) -> Result<(), ErrorReason> {
         match self {
-            Operand::Register(reg) => registers.set_register_value_8(*reg, value),
+            Operand::Register(reg) if self.get_bit_length() == 8 => {
+                registers.set_register_value_8(*reg, value)?
+            }
             Operand::Memory(addr) => memory.write(*addr, value),
-            _ => panic!("Invalid operand for write"),
+            _ => return Err(ErrorReason::InvalidOperand(*self)),
         }
+        Ok(())
     }
-    pub fn write_u16(&self, value: u16, registers: &mut Registers, memory: &mut MMU) {
+    pub fn write_u16(
+        &self,
+        value: u16,
+        registers: &mut Registers,
+        memory: &mut MMU,
+    ) -> Result<(), ErrorReason> {
         match self {
-            Operand::Register(reg) => registers.set_register_value_16(*reg, value),
-            Operand::Memory(addr) => memory.write(*addr, value as u8),
+            Operand::Register(reg) if self.get_bit_length() == 16 => {
+                registers.set_register_value_16(*reg, value)?
+            }
+            Operand::Memory(addr) => memory.write_word(*addr, value),
 
-            _ => panic!("Invalid register for 16-bit write {:?}", self),
+            _ => return Err(ErrorReason::InvalidOperand(*self)),
         }
+        Ok(())
     }
-    pub fn read_16(&self, registers: &Registers, memory: &mut MMU) -> u16 {
+    pub fn read_16(&self, registers: &Registers, memory: &mut MMU) -> Result<u16, ErrorReason> {
         match self {
-            Operand::Register(reg) => registers.get_register_value_16(*reg),
-            Operand::Immediate16(value) => *value,
-            Operand::Memory(addr) => memory.read(*addr) as u16,
-            _ => panic!("Invalid register for 16-bit read {:?}", self),
+            Operand::Register(reg) if self.get_bit_length() == 16 => {
+                registers.get_register_value_16(*reg)
+            }
+            Operand::Immediate16(value) => Ok(*value),
+            Operand::Memory(addr) => Ok(memory.read_word(*addr)),
+            _ => Err(ErrorReason::InvalidOperand(*self)),
         }
     }
     pub fn get_bit_length(&self) -> u8 {
@@ -292,23 +331,25 @@ impl Operand {
                 RegisterNames::SP => 16,
                 RegisterNames::PC => 16,
             },
-            Operand::Memory(i) => {
-                if *i > 255 {
-                    16
-                } else {
-                    8
-                }
-            }
+            // Memory operands address a single byte, except for LD (a16),SP
+            // which is handled by its 16-bit source
+            Operand::Memory(_) => 8,
             Operand::Immediate(_) => 8,
             Operand::Immediate16(_) => 16,
+            Operand::Offset(_) => 8,
+            Operand::SpOffset(_) => 16,
             Operand::Flag(_) => 16,
             _ => 0,
         }
     }
 }
 
-pub fn inc_8bit(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn inc_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = value.wrapping_add(1);
 
     // Set flags
@@ -317,10 +358,15 @@ pub fn inc_8bit(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.h = (value & 0x0F) == 0x0F;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
-pub fn dec_8bit(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn dec_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = value.wrapping_sub(1);
 
     // Set flags
@@ -329,38 +375,48 @@ pub fn dec_8bit(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.h = (value & 0x0F) == 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn inc_16bit(registers: &mut Registers, operand: Operand, memory: &mut MMU) {
-    let value = operand.read_16(registers, memory);
+pub fn inc_16bit(
+    registers: &mut Registers,
+    operand: Operand,
+    memory: &mut MMU,
+) -> Result<(), ErrorReason> {
+    let value = operand.read_16(registers, memory)?;
     let result = value.wrapping_add(1);
 
-    // Set flags
-    registers.flag.z = result == 0;
-    registers.flag.n = false;
-    registers.flag.h = (value & 0x0FFF) == 0x0FFF;
+    // 16 bit increments don't affect any flags
 
     // Write result back to the operand
-    operand.write_u16(result, registers, memory);
+    operand.write_u16(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn dec_16bit(registers: &mut Registers, operand: Operand, memory: &mut MMU) {
-    let value = operand.read_16(registers, memory);
+pub fn dec_16bit(
+    registers: &mut Registers,
+    operand: Operand,
+    memory: &mut MMU,
+) -> Result<(), ErrorReason> {
+    let value = operand.read_16(registers, memory)?;
     let result = value.wrapping_sub(1);
 
-    // Set flags
-    registers.flag.z = result == 0;
-    registers.flag.n = true;
-    registers.flag.h = (value & 0x0FFF) == 0;
+    // 16 bit decrements don't affect any flags
 
     // Write result back to the operand
-    operand.write_u16(result, registers, memory);
+    operand.write_u16(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn add_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+pub fn add_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand1: Operand,
+    operand2: Operand,
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let result = value1.wrapping_add(value2);
 
     // Set flags
@@ -370,12 +426,18 @@ pub fn add_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand,
     registers.flag.c = (value1 as u16 + value2 as u16) > 0xFF;
 
     // Write result back to the first operand
-    operand1.write(result, registers, memory);
+    operand1.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn adc_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+pub fn adc_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand1: Operand,
+    operand2: Operand,
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let carry = if registers.flag.c { 1 } else { 0 };
     let result = value1.wrapping_add(value2).wrapping_add(carry);
 
@@ -386,7 +448,8 @@ pub fn adc_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand,
     registers.flag.c = (value1 as u16 + value2 as u16 + carry as u16) > 0xFF;
 
     // Write result back to the first operand
-    operand1.write(result, registers, memory);
+    operand1.write(result, registers, memory)?;
+    Ok(())
 }
 
 pub fn add_16bit(
@@ -394,24 +457,52 @@ pub fn add_16bit(
     operand1: Operand,
     operand2: Operand,
     memory: &mut MMU,
-) {
-    let value1 = operand1.read_16(registers, memory);
-    let value2 = operand2.read_16(registers, memory);
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read_16(registers, memory)?;
+    let value2 = operand2.read_16(registers, memory)?;
     let result = value1.wrapping_add(value2);
 
-    // Set flags
-    registers.flag.z = result == 0;
+    // Set flags, Z is left untouched
     registers.flag.n = false;
     registers.flag.h = ((value1 & 0x0FFF) + (value2 & 0x0FFF)) > 0x0FFF;
     registers.flag.c = (value1 as u32 + value2 as u32) > 0xFFFF;
 
     // Write result back to the first operand
-    operand1.write_u16(result, registers, memory);
+    operand1.write_u16(result, registers, memory)?;
+    Ok(())
+}
+
+/// SP plus a signed offset, setting the flags shared by ADD SP,e8 and LD HL,SP+e8
+fn sp_offset(registers: &mut Registers, offset: i8) -> u16 {
+    let sp = registers.sp;
+    let value = offset as u8 as u16;
+
+    // Half carry and carry come from the unsigned addition to the low byte
+    registers.flag.z = false;
+    registers.flag.n = false;
+    registers.flag.h = ((sp & 0x0F) + (value & 0x0F)) > 0x0F;
+    registers.flag.c = ((sp & 0xFF) + value) > 0xFF;
+
+    sp.wrapping_add(offset as u16)
 }
 
-pub fn sub_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+pub fn add_sp_offset(registers: &mut Registers, offset: i8) {
+    registers.sp = sp_offset(registers, offset);
+}
+
+pub fn ld_hl_sp_offset(registers: &mut Registers, offset: i8) -> Result<(), ErrorReason> {
+    let result = sp_offset(registers, offset);
+    registers.set_register_value_16(RegisterNames::HL, result)
+}
+
+pub fn sub_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand1: Operand,
+    operand2: Operand,
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let result = value1.wrapping_sub(value2);
 
     // Set flags
@@ -421,12 +512,18 @@ pub fn sub_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand,
     registers.flag.c = (value1 as u16) < (value2 as u16);
 
     // Write result back to the first operand
-    operand1.write(result, registers, memory);
+    operand1.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn sbc_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+pub fn sbc_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand1: Operand,
+    operand2: Operand,
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let carry = if registers.flag.c { 1 } else { 0 };
     let result = value1.wrapping_sub(value2).wrapping_sub(carry);
 
@@ -437,32 +534,18 @@ pub fn sbc_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand,
     registers.flag.c = (value1 as u16) < (value2 as u16) + carry as u16;
 
     // Write result back to the first operand
-    operand1.write(result, registers, memory);
+    operand1.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn sub_16bit(
+pub fn and_8bit(
     registers: &mut Registers,
+    memory: &mut MMU,
     operand1: Operand,
     operand2: Operand,
-    memory: &mut MMU,
-) {
-    let value1 = operand1.read_16(registers, memory);
-    let value2 = operand2.read_16(registers, memory);
-    let result = value1.wrapping_sub(value2);
-
-    // Set flags
-    registers.flag.z = result == 0;
-    registers.flag.n = true;
-    registers.flag.h = (value1 & 0x0FFF) < (value2 & 0x0FFF);
-    registers.flag.c = (value1 as u32) < (value2 as u32);
-
-    // Write result back to the first operand
-    operand1.write_u16(result, registers, memory);
-}
-
-pub fn and_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let result = value1 & value2;
 
     // Set flags
@@ -472,12 +555,18 @@ pub fn and_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand,
     registers.flag.c = false;
 
     // Write result back to the first operand
-    operand1.write(result, registers, memory);
+    operand1.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn or_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+pub fn or_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand1: Operand,
+    operand2: Operand,
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let result = value1 | value2;
 
     // Set flags
@@ -487,12 +576,18 @@ pub fn or_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, o
     registers.flag.c = false;
 
     // Write result back to the first operand
-    operand1.write(result, registers, memory);
+    operand1.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn xor_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+pub fn xor_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand1: Operand,
+    operand2: Operand,
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let result = value1 ^ value2;
 
     // Set flags
@@ -502,11 +597,17 @@ pub fn xor_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand,
     registers.flag.c = false;
 
     // Write result back to the first operand
-    operand1.write(result, registers, memory);
+    operand1.write(result, registers, memory)?;
+    Ok(())
 }
-pub fn cp_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, operand2: Operand) {
-    let value1 = operand1.read(registers, memory);
-    let value2 = operand2.read(registers, memory);
+pub fn cp_8bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand1: Operand,
+    operand2: Operand,
+) -> Result<(), ErrorReason> {
+    let value1 = operand1.read(registers, memory)?;
+    let value2 = operand2.read(registers, memory)?;
     let result = value1.wrapping_sub(value2);
 
     // Set flags
@@ -514,22 +615,35 @@ pub fn cp_8bit(registers: &mut Registers, memory: &mut MMU, operand1: Operand, o
     registers.flag.n = true;
     registers.flag.h = (value1 & 0x0F) < (value2 & 0x0F);
     registers.flag.c = (value1 as u16) < (value2 as u16);
+    Ok(())
 }
 
-pub fn ld(registers: &mut Registers, operand1: Operand, operand2: Operand, memory: &mut MMU) {
-    let mut value: u16 = 0;
-    if operand2.get_bit_length() == 8 {
-        value = operand2.read(registers, memory) as u16;
+pub fn ld(
+    registers: &mut Registers,
+    operand1: Operand,
+    operand2: Operand,
+    memory: &mut MMU,
+) -> Result<(), ErrorReason> {
+    let src_len = operand2.get_bit_length();
+    let value = if src_len == 8 {
+        operand2.read(registers, memory)? as u16
     } else {
-        value = operand2.read_16(registers, memory);
-    }
-    if operand1.get_bit_length() == 16 {
-        operand1.write_u16(value, registers, memory);
+        operand2.read_16(registers, memory)?
+    };
+    // LD (a16),SP stores both bytes of SP
+    if operand1.get_bit_length() == 16 || src_len == 16 {
+        operand1.write_u16(value, registers, memory)?;
     } else {
-        operand1.write(value as u8, registers, memory);
+        operand1.write(value as u8, registers, memory)?;
     }
+    Ok(())
 }
-pub fn ldh(registers: &mut Registers, operand1: Operand, operand2: Operand, memory: &mut MMU) {
+pub fn ldh(
+    registers: &mut Registers,
+    operand1: Operand,
+    operand2: Operand,
+    memory: &mut MMU,
+) -> Result<(), ErrorReason> {
     // Calculate the high memory address - always 0xFF00 + offset
     let addr = match operand1 {
         Operand::Register(RegisterNames::A) => {
@@ -537,30 +651,35 @@ pub fn ldh(registers: &mut Registers, operand1: Operand, operand2: Operand, memo
             let offset = match operand2 {
                 Operand::Immediate(imm) => imm as u16,
                 Operand::Register(RegisterNames::C) => {
-                    registers.get_register_value_8(RegisterNames::C) as u16
+                    registers.get_register_value_8(RegisterNames::C)? as u16
                 }
-                _ => panic!("Invalid operand for LDH instruction"),
+                _ => return Err(ErrorReason::InvalidOperand(operand2)),
             };
             let addr = 0xFF00 + offset;
             let value = memory.read(addr);
-            registers.set_register_value_8(RegisterNames::A, value);
-            return;
+            registers.set_register_value_8(RegisterNames::A, value)?;
+            return Ok(());
         }
         Operand::Immediate(imm) => 0xFF00 + imm as u16,
         Operand::Register(RegisterNames::C) => {
-            0xFF00 + registers.get_register_value_8(RegisterNames::C) as u16
+            0xFF00 + registers.get_register_value_8(RegisterNames::C)? as u16
         }
-        _ => panic!("Invalid operand for LDH instruction"),
+        _ => return Err(ErrorReason::InvalidOperand(operand1)),
     };
 
     // Store the value from register A to the high memory address
-    let value = registers.get_register_value_8(RegisterNames::A);
+    let value = registers.get_register_value_8(RegisterNames::A)?;
     memory.write(addr, value);
+    Ok(())
 }
 
 // rlc : rotate left circular
-pub fn rlc(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn rlc(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = (value << 1) | (value >> 7);
 
     // Set flags
@@ -570,11 +689,16 @@ pub fn rlc(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = (value & 0x80) != 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn rrc(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn rrc(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = (value >> 1) | (value << 7);
 
     // Set flags
@@ -584,11 +708,16 @@ pub fn rrc(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = (value & 0x01) != 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn rl(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn rl(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let carry = registers.flag.c as u8;
     let result = (value << 1) | carry;
 
@@ -599,11 +728,16 @@ pub fn rl(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = (value & 0x80) != 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
-pub fn rr(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn rr(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let carry = registers.flag.c as u8;
     let result = (value >> 1) | (carry << 7);
 
@@ -614,12 +748,17 @@ pub fn rr(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = (value & 0x01) != 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
 // shift left arithmetic
-pub fn sla(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn sla(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = value << 1;
 
     // Set flags
@@ -629,11 +768,16 @@ pub fn sla(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = (value & 0x80) != 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 // shift right arithmetic
-pub fn sra(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn sra(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = (value >> 1) | (value & 0x80);
 
     // Set flags
@@ -643,12 +787,17 @@ pub fn sra(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = (value & 0x01) != 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
 // Swap nibbles
-pub fn swap(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn swap(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = (value << 4) | (value >> 4);
 
     // Set flags
@@ -658,12 +807,18 @@ pub fn swap(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = false;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
 // Bit test
-pub fn bit(registers: &mut Registers, memory: &mut MMU, operand: Operand, bit: u8) {
-    let value = operand.read(registers, memory);
+pub fn bit(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+    bit: u8,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = value & (1 << bit);
 
     // Set flags
@@ -671,29 +826,46 @@ pub fn bit(registers: &mut Registers, memory: &mut MMU, operand: Operand, bit: u
     registers.flag.n = false;
     registers.flag.h = true;
     registers.flag.c = false;
+    Ok(())
 }
 
 // Bit set
-pub fn set(registers: &mut Registers, memory: &mut MMU, operand: Operand, bit: u8) {
-    let value = operand.read(registers, memory);
+pub fn set(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+    bit: u8,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = value | (1 << bit);
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
 // Bit reset
-pub fn res(registers: &mut Registers, memory: &mut MMU, operand: Operand, bit: u8) {
-    let value = operand.read(registers, memory);
+pub fn res(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+    bit: u8,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = value & !(1 << bit);
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
 // Shift right logical
-pub fn srl(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read(registers, memory);
+pub fn srl(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read(registers, memory)?;
     let result = value >> 1;
 
     // Set flags
@@ -703,36 +875,55 @@ pub fn srl(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
     registers.flag.c = (value & 0x01) != 0;
 
     // Write result back to the operand
-    operand.write(result, registers, memory);
+    operand.write(result, registers, memory)?;
+    Ok(())
 }
 
 // Control flow instructions
 
-pub fn jp(registers: &mut Registers, memory: &mut MMU, operand: Operand, condition: bool) {
+pub fn jp(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+    condition: bool,
+) -> Result<(), ErrorReason> {
     if condition {
-        let address = operand.read_16(registers, memory);
+        let address = operand.read_16(registers, memory)?;
         let pc = &mut registers.pc;
         *pc = address;
     }
+    Ok(())
 }
 
-pub fn call(registers: &mut Registers, memory: &mut MMU, operand: Operand, condition: bool) {
+pub fn call(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+    condition: bool,
+) -> Result<(), ErrorReason> {
     if condition {
-        let address = operand.read_16(registers, memory);
+        let address = operand.read_16(registers, memory)?;
         let pc = &mut registers.pc;
         let sp = &mut registers.sp;
         *sp = sp.wrapping_sub(2);
         memory.write_word(*sp, *pc);
         *pc = address;
     }
+    Ok(())
 }
 
-pub fn jr(registers: &mut Registers, memory: &mut MMU, operand: Operand, condition: bool) {
+pub fn jr(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+    condition: bool,
+) -> Result<(), ErrorReason> {
     if condition {
-        let offset = operand.read(registers, memory) as i8;
+        let offset = operand.read(registers, memory)? as i8;
         let pc = &mut registers.pc;
         *pc = pc.wrapping_add(offset as u16);
     }
+    Ok(())
 }
 
 pub fn ret(registers: &mut Registers, memory: &mut MMU, cond: bool) {
@@ -740,59 +931,48 @@ pub fn ret(registers: &mut Registers, memory: &mut MMU, cond: bool) {
     if cond {
         let sp = &mut registers.sp;
         let low = memory.read(*sp);
-        let high = memory.read(*sp + 1);
+        let high = memory.read(sp.wrapping_add(1));
         let pc = &mut registers.pc;
         *pc = ((high as u16) << 8) | (low as u16);
         *sp = sp.wrapping_add(2);
     }
 }
 
-pub fn daa(registers: &mut Registers) {
-    let mut a = registers.af >> 8;
-    a &= 0xFF; // Ensure a is 8 bits
-    let mut carry = 0;
+pub fn daa(registers: &mut Registers) -> Result<(), ErrorReason> {
+    let mut a = registers.get_register_value_8(RegisterNames::A)?;
+    let mut carry = registers.flag.c;
 
     if registers.flag.n {
+        // Adjust after a subtraction, the carry is left as it was
         if registers.flag.c {
             a = a.wrapping_sub(0x60);
-            carry = 1;
         }
         if registers.flag.h {
             a = a.wrapping_sub(0x06);
-            carry = 1;
         }
     } else {
         if registers.flag.c || a > 0x99 {
             a = a.wrapping_add(0x60);
-            carry = 1;
+            carry = true;
         }
         if registers.flag.h || (a & 0x0F) > 0x09 {
             a = a.wrapping_add(0x06);
-            carry = 1;
         }
     }
 
-    registers.af = (registers.af & 0xFF00) | (a << 8);
+    registers.set_register_value_8(RegisterNames::A, a)?;
     registers.flag.z = a == 0;
     registers.flag.h = false;
-    registers.flag.c = carry != 0;
-}
-
-/// Disables interrupts by clearing the IME flag
-pub fn di(registers: &mut Registers) {
-    // The DI instruction disables interrupts by clearing the IME (Interrupt Master Enable) flag
-    // This prevents the CPU from responding to any interrupts
-
-    // Note: DI doesn't immediately disable interrupts, it actually disables them after
-    // the instruction following DI is executed, but this simplified implementation
-    // disables them immediately
+    registers.flag.c = carry;
+    Ok(())
 }
 
-pub fn cpl(registers: &mut Registers) {
-    let a = registers.af >> 8;
-    registers.af = (registers.af & 0xFF00) | (!a << 8);
+pub fn cpl(registers: &mut Registers) -> Result<(), ErrorReason> {
+    let a = registers.get_register_value_8(RegisterNames::A)?;
+    registers.set_register_value_8(RegisterNames::A, !a)?;
     registers.flag.n = true;
     registers.flag.h = true;
+    Ok(())
 }
 pub fn scf(registers: &mut Registers) {
     registers.flag.n = false;
@@ -805,16 +985,26 @@ pub fn ccf(registers: &mut Registers) {
     registers.flag.c = !registers.flag.c;
 }
 
-pub fn push(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
-    let value = operand.read_16(registers, memory);
+pub fn push(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
+    let value = operand.read_16(registers, memory)?;
     let sp = &mut registers.sp;
     *sp = sp.wrapping_sub(2);
     memory.write_word(*sp, value);
+    Ok(())
 }
 
-pub fn pop(registers: &mut Registers, memory: &mut MMU, operand: Operand) {
+pub fn pop(
+    registers: &mut Registers,
+    memory: &mut MMU,
+    operand: Operand,
+) -> Result<(), ErrorReason> {
     let sp = &mut registers.sp;
     let value = memory.read_word(*sp);
     *sp = sp.wrapping_add(2);
-    operand.write_u16(value, registers, memory);
+    operand.write_u16(value, registers, memory)?;
+    Ok(())
 }
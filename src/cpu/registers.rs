@@ -1,5 +1,4 @@
-use crate::cpu::shared::{Flag, FlagNames, RegisterNames, Registers};
-use core::panic;
+use crate::cpu::shared::{ErrorReason, Flag, FlagNames, Operand, RegisterNames, Registers};
 impl Flag {
     pub fn new() -> Self {
         Self {
@@ -49,6 +48,14 @@ impl Flag {
         self.c = false;
     }
 
+    /// Flags as the F register, the lower nibble always reads 0
+    pub fn get_flag_value(&self) -> u8 {
+        ((self.z as u8) << 7)
+            | ((self.n as u8) << 6)
+            | ((self.h as u8) << 5)
+            | ((self.c as u8) << 4)
+    }
+
     pub fn set_flag_value(&mut self, value: u8) {
         let flag_reg = value >> 4;
         let new_z: bool = ((flag_reg & 0b1000) >> 3) == 1;
@@ -77,8 +84,8 @@ impl Registers {
         }
     }
 
-    pub fn get_register_value_8(&self, register: RegisterNames) -> u8 {
-        match register {
+    pub fn get_register_value_8(&self, register: RegisterNames) -> Result<u8, ErrorReason> {
+        let value = match register {
             RegisterNames::A => (self.af >> 8) as u8,
             RegisterNames::B => (self.bc >> 8) as u8,
             RegisterNames::C => (self.bc & 0xFF) as u8,
@@ -86,34 +93,48 @@ impl Registers {
             RegisterNames::E => (self.de & 0xFF) as u8,
             RegisterNames::H => (self.hl >> 8) as u8,
             RegisterNames::L => (self.hl & 0xFF) as u8,
-            _ => panic!("Invalid register {:?}", register),
-        }
-    }
-    pub fn set_register_value_16(&mut self, register: RegisterNames, value: u16) {
+            _ => return Err(ErrorReason::InvalidOperand(Operand::Register(register))),
+        };
+        Ok(value)
+    }
+    pub fn set_register_value_16(
+        &mut self,
+        register: RegisterNames,
+        value: u16,
+    ) -> Result<(), ErrorReason> {
         match register {
             RegisterNames::AF => {
-                self.af = value;
+                self.af = value & 0xFFF0;
                 self.flag.set_flag_value((value & 0xff) as u8);
             }
             RegisterNames::BC => self.bc = value,
             RegisterNames::DE => self.de = value,
             RegisterNames::HL => self.hl = value,
             RegisterNames::SP => self.sp = value,
+            RegisterNames::PC => self.pc = value,
 
-            _ => panic!("Invalid register"),
+            _ => return Err(ErrorReason::InvalidOperand(Operand::Register(register))),
         }
+        Ok(())
     }
-    pub fn get_register_value_16(&self, register: RegisterNames) -> u16 {
-        match register {
-            RegisterNames::AF => self.af,
+    pub fn get_register_value_16(&self, register: RegisterNames) -> Result<u16, ErrorReason> {
+        let value = match register {
+            // F lives in `flag`, the low byte of `af` isn't kept up to date
+            RegisterNames::AF => (self.af & 0xFF00) | self.flag.get_flag_value() as u16,
             RegisterNames::BC => self.bc,
             RegisterNames::DE => self.de,
             RegisterNames::HL => self.hl,
             RegisterNames::SP => self.sp,
-            _ => panic!("Invalid register"),
-        }
-    }
-    pub fn set_register_value_8(&mut self, register: RegisterNames, value: u8) {
+            RegisterNames::PC => self.pc,
+            _ => return Err(ErrorReason::InvalidOperand(Operand::Register(register))),
+        };
+        Ok(value)
+    }
+    pub fn set_register_value_8(
+        &mut self,
+        register: RegisterNames,
+        value: u8,
+    ) -> Result<(), ErrorReason> {
         match register {
             RegisterNames::A => self.af = (self.af & 0xFF) | ((value as u16) << 8),
             RegisterNames::B => self.bc = (self.bc & 0x00FF) | ((value as u16) << 8),
@@ -122,8 +143,9 @@ impl Registers {
             RegisterNames::E => self.de = (self.de & 0xFF00) | (value as u16),
             RegisterNames::H => self.hl = (self.hl & 0x00FF) | ((value as u16) << 8),
             RegisterNames::L => self.hl = (self.hl & 0xFF00) | (value as u16),
-            _ => panic!("Invalid register"),
+            _ => return Err(ErrorReason::InvalidOperand(Operand::Register(register))),
         }
+        Ok(())
     }
     pub fn get_flag(&self, flag: &FlagNames) -> bool {
         match flag {
@@ -1,4 +1,4 @@
-use json;
+use std::fmt;
 
 pub const DEB: bool = true;
 
@@ -12,7 +12,7 @@ pub struct Registers {
     pub flag: Flag,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum RegisterNames {
     A,
     B,
@@ -28,7 +28,7 @@ pub enum RegisterNames {
     SP,
     PC,
 }
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum FlagNames {
     Z,
     N,
@@ -38,7 +38,7 @@ pub enum FlagNames {
     NC,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Instruction {
     // Single byte instructions
     NOP,
@@ -79,6 +79,12 @@ pub enum Instruction {
 
     // Other instructions
     DI,
+    EI,
+    HALT,
+    STOP,
+
+    // Unused opcodes (0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD)
+    ILLEGAL,
 
     // PREFIX instruction (0xCB)
     PREFIX,
@@ -97,139 +103,141 @@ pub enum Instruction {
     SET,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Operand {
     Register(RegisterNames),
     Memory(u16),      // Memory address
     Immediate(u8),    // Immediate value
     Immediate16(u16), // 16-bit immediate value
+    Offset(i8),       // Signed 8-bit immediate (e8)
+    SpOffset(i8),     // SP plus a signed 8-bit immediate (SP+e8)
     Flag(FlagNames),
     NIL,
 }
 
-pub struct Flag {
-    pub z: bool,
-    pub n: bool,
-    pub h: bool,
-    pub c: bool,
+/// Why an instruction couldn't be executed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorReason {
+    UnhandledInstruction(Instruction),
+    InvalidOperand(Operand),
 }
 
-pub fn get_opcodes() -> json::JsonValue {
-    // Load the opcodes from the JSON file
-    let opcodes = include_str!("opcodes.json");
-    json::parse(opcodes).unwrap()
+/// Error returned by `CPU::step`, with the PC and opcode of the failing instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmulationError {
+    Unprefixed {
+        pc: u16,
+        opcode: u8,
+        reason: ErrorReason,
+    },
+    // opcode is the byte following 0xCB
+    Prefixed {
+        pc: u16,
+        opcode: u8,
+        reason: ErrorReason,
+    },
 }
 
-// Helper functions
-pub fn match_string_to_instruction(instr: &str) -> Instruction {
-    // match the instruction to the enum
-    match instr {
-        "ADD" => Instruction::ADD,
-        "ADC" => Instruction::ADC,
-        "SUB" => Instruction::SUB,
-        "SBC" => Instruction::SBC,
-        "AND" => Instruction::AND,
-        "OR" => Instruction::OR,
-        "XOR" => Instruction::XOR,
-        "CP" => Instruction::CP,
-        "DAA" => Instruction::DAA,
-        "CPL" => Instruction::CPL,
-        "SCF" => Instruction::SCF,
-        "CCF" => Instruction::CCF,
-        "NOP" => Instruction::NOP,
-        "INC" => Instruction::INC,
-        "DEC" => Instruction::DEC,
-        "LD" => Instruction::LD,
-        "LDH" => Instruction::LDH,
-        "RRCA" => Instruction::RRCA,
-        "RLA" => Instruction::RLA,
-        "RRA" => Instruction::RRA,
-        "RLCA" => Instruction::RLCA,
-        "PUSH" => Instruction::PUSH,
-        "POP" => Instruction::POP,
-        "JP" => Instruction::JP,
-        "JR" => Instruction::JR,
-        "CALL" => Instruction::CALL,
-        "RET" => Instruction::RET,
-        "DI" => Instruction::DI,
-        "PREFIX" => Instruction::PREFIX,
-        // CB Prefixed instructions
-        "RLC" => Instruction::RLC,
-        "RRC" => Instruction::RRC,
-        "RL" => Instruction::RL,
-        "RR" => Instruction::RR,
-        "SLA" => Instruction::SLA,
-        "SRA" => Instruction::SRA,
-        "SRL" => Instruction::SRL,
-        "SWAP" => Instruction::SWAP,
-        "BIT" => Instruction::BIT,
-        "RES" => Instruction::RES,
-        "SET" => Instruction::SET,
-        _ => panic!("Unknown instruction: {}", instr),
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulationError::Unprefixed { pc, opcode, reason } => write!(
+                f,
+                "Failed to execute opcode 0x{:02X} at 0x{:04X}: {:?}",
+                opcode, pc, reason
+            ),
+            EmulationError::Prefixed { pc, opcode, reason } => write!(
+                f,
+                "Failed to execute opcode 0xCB 0x{:02X} at 0x{:04X}: {:?}",
+                opcode, pc, reason
+            ),
+        }
     }
 }
 
-pub fn match_string_preinstruction(instr: &str) -> Instruction {
-    match instr {
-        "RLC" => Instruction::RLC,
-        "RRC" => Instruction::RRC,
-        "RL" => Instruction::RL,
-        "RR" => Instruction::RR,
-        "SLA" => Instruction::SLA,
-        "SRA" => Instruction::SRA,
-        "SWAP" => Instruction::SWAP,
-        "SRL" => Instruction::SRL,
-        "BIT" => Instruction::BIT,
-        "RES" => Instruction::RES,
-        "SET" => Instruction::SET,
-        _ => panic!("Unknown CB-prefixed instruction: {}", instr),
-    }
+impl std::error::Error for EmulationError {}
+
+pub struct Flag {
+    pub z: bool,
+    pub n: bool,
+    pub h: bool,
+    pub c: bool,
 }
 
-pub fn match_string_to_register(reg: &str) -> RegisterNames {
-    // match the register to the enum
-    match reg {
-        "A" => RegisterNames::A,
-        "B" => RegisterNames::B,
-        "C" => RegisterNames::C,
-        "D" => RegisterNames::D,
-        "E" => RegisterNames::E,
-        "H" => RegisterNames::H,
-        "L" => RegisterNames::L,
-
-        _ => match_string_to_register16(reg),
-    }
+/// Kind of operand an opcode takes, decoded from `opcodes.json` at build time
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    None,
+    Register(RegisterNames),    // A, BC, SP, ...
+    Indirect(RegisterNames),    // (BC), (HL), (C)
+    IndirectInc(RegisterNames), // (HL+)
+    IndirectDec(RegisterNames), // (HL-)
+    Imm8,                       // n8
+    Imm16,                      // n16, or a16 used as a jump target
+    Offset8,                    // e8, signed
+    Addr8,                      // (a8), offset into 0xFF00-0xFFFF
+    Addr16,                     // (a16)
+    SpOffset,                   // SP+e8
+    Condition(FlagNames),       // Z, NZ, C, NC on jumps, calls and returns
+    Vector(u8),                 // RST target
+    Bit(u8),                    // Bit index for BIT, RES and SET
 }
-pub fn match_string_to_register16(reg: &str) -> RegisterNames {
-    // match the register to the enum
-    match reg {
-        "AF" => RegisterNames::AF,
-        "BC" => RegisterNames::BC,
-        "DE" => RegisterNames::DE,
-        "HL" => RegisterNames::HL,
-        "SP" => RegisterNames::SP,
-        "PC" => RegisterNames::PC,
-
-        _ => panic!("Unknown register: {}", reg),
-    }
+
+/// Decoded entry of the opcode table
+#[derive(Copy, Clone, Debug)]
+pub struct OpcodeInfo {
+    pub mnemonic: Instruction,
+    pub operands: [OperandKind; 2],
+    // Length in bytes, including the opcode (and the 0xCB prefix)
+    pub bytes: u8,
+    // T-cycles when a branch is taken and when it is not,
+    // both the same for unconditional instructions
+    pub cycles: [u8; 2],
 }
 
-pub fn match_string_to_flag(flag: &str) -> FlagNames {
-    match flag {
-        "Z" => FlagNames::Z,
-        "H" => FlagNames::H,
-        "C" => FlagNames::C,
-        "N" => FlagNames::N,
-        "NZ" => FlagNames::NZ,
-        "NC" => FlagNames::NC,
-        _ => panic!("Invalid Flag type {:?}", flag),
+// UNPREFIXED and CB_PREFIXED, indexed by opcode
+include!(concat!(env!("OUT_DIR"), "/opcode_table.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditional_cycles() {
+        let jp_nz = &UNPREFIXED[0xC2];
+        assert_eq!(jp_nz.mnemonic, Instruction::JP);
+        assert_eq!(
+            jp_nz.operands,
+            [OperandKind::Condition(FlagNames::NZ), OperandKind::Imm16]
+        );
+        assert_eq!(jp_nz.bytes, 3);
+        assert_eq!(jp_nz.cycles, [16, 12]);
     }
-}
 
-pub fn is_flag(operand: &str, instr: &Instruction) -> bool {
-    return (operand.contains("Z") || operand.contains("NZ") || operand.contains("C"))
-        && (*instr == Instruction::JP
-            || *instr == Instruction::CALL
-            || *instr == Instruction::RET
-            || *instr == Instruction::JR);
+    #[test]
+    fn test_decoded_operands() {
+        assert_eq!(
+            UNPREFIXED[0xF8].operands,
+            [
+                OperandKind::Register(RegisterNames::HL),
+                OperandKind::SpOffset
+            ]
+        );
+        assert_eq!(
+            UNPREFIXED[0x3A].operands,
+            [
+                OperandKind::Register(RegisterNames::A),
+                OperandKind::IndirectDec(RegisterNames::HL)
+            ]
+        );
+        assert_eq!(UNPREFIXED[0xFD].mnemonic, Instruction::ILLEGAL);
+        assert_eq!(
+            CB_PREFIXED[0x7E].operands,
+            [
+                OperandKind::Bit(7),
+                OperandKind::Indirect(RegisterNames::HL)
+            ]
+        );
+        assert_eq!(CB_PREFIXED[0x7E].cycles, [12, 12]);
+    }
 }
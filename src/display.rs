@@ -0,0 +1,172 @@
+use puro_boy::mmu::{FRAMEBUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use puro_boy::MMU;
+use sdl3::event::Event;
+use sdl3::keyboard::Keycode;
+use sdl3::pixels::{Color, PixelFormat};
+use sdl3::render::{FRect, ScaleMode, Texture, TextureCreator, WindowCanvas};
+use sdl3::sys::pixels::SDL_PixelFormat;
+use sdl3::video::{FullscreenType, WindowContext};
+
+pub const MIN_SCALE: u32 = 1;
+pub const MAX_SCALE: u32 = 8;
+
+const SCALE_DOWN_KEY: Keycode = Keycode::F2;
+const SCALE_UP_KEY: Keycode = Keycode::F3;
+const FULLSCREEN_KEY: Keycode = Keycode::F11;
+
+/// Presents the frames the PPU finishes, scaled to fit the window
+pub struct Display<'a> {
+    canvas: WindowCanvas,
+    // Streaming texture the size of the Game Boy screen, uploaded once per
+    // frame and stretched over the window by the GPU
+    texture: Texture<'a>,
+    // RGBA8888 copy of the last frame
+    rgba: Box<[u8; FRAMEBUFFER_SIZE * 4]>,
+    // Window size as a multiple of the Game Boy screen
+    scale: u32,
+}
+
+impl<'a> Display<'a> {
+    pub fn new(
+        mut canvas: WindowCanvas,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        scale: u32,
+    ) -> Self {
+        // Bytes in R, G, B, A order whatever the host endianness, matching
+        // PPU::framebuffer_rgba
+        let format =
+            PixelFormat::try_from(SDL_PixelFormat::RGBA32).expect("Couldn't get RGBA pixel format");
+        let mut texture = texture_creator
+            .create_texture_streaming(format, SCREEN_WIDTH, SCREEN_HEIGHT)
+            .expect("Couldn't create frame texture");
+        // Keep the pixels sharp when scaling up
+        texture.set_scale_mode(ScaleMode::Nearest);
+
+        if let Err(err) = canvas
+            .window_mut()
+            .set_minimum_size(SCREEN_WIDTH, SCREEN_HEIGHT)
+        {
+            log::warn!("Couldn't set minimum window size: {}", err);
+        }
+
+        let mut display = Display {
+            canvas,
+            texture,
+            rgba: Box::new([0; FRAMEBUFFER_SIZE * 4]),
+            scale: MIN_SCALE,
+        };
+        display.set_scale(scale);
+        display
+    }
+
+    /// Resize the window to a multiple of the Game Boy screen, 1x-8x
+    pub fn set_scale(&mut self, scale: u32) {
+        self.scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+        if self.is_fullscreen() {
+            return;
+        }
+        let window = self.canvas.window_mut();
+        if let Err(err) = window.set_size(SCREEN_WIDTH * self.scale, SCREEN_HEIGHT * self.scale) {
+            log::warn!("Couldn't resize window: {}", err);
+        }
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let fullscreen = !self.is_fullscreen();
+        if let Err(err) = self.canvas.window_mut().set_fullscreen(fullscreen) {
+            log::warn!("Couldn't toggle fullscreen: {}", err);
+        }
+        // Going back to a window restores the chosen scale
+        if !fullscreen {
+            self.set_scale(self.scale);
+        }
+    }
+
+    /// Handle the scaling and fullscreen keys, returns true if the event
+    /// was used
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let Event::KeyDown {
+            keycode: Some(keycode),
+            repeat: false,
+            ..
+        } = *event
+        else {
+            return false;
+        };
+        match keycode {
+            SCALE_DOWN_KEY => self.set_scale(self.scale.saturating_sub(1)),
+            SCALE_UP_KEY => self.set_scale(self.scale + 1),
+            FULLSCREEN_KEY => self.toggle_fullscreen(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Upload the PPU's framebuffer and show it, letterboxed in the window
+    pub fn present(&mut self, mmu: &MMU) {
+        if let Err(err) = self.draw(mmu) {
+            log::error!("Couldn't draw frame: {}", err);
+        }
+        self.canvas.present();
+    }
+
+    fn draw(&mut self, mmu: &MMU) -> Result<(), String> {
+        mmu.get_ppu().framebuffer_rgba(&mut self.rgba);
+        self.texture
+            .update(None, &self.rgba[..], SCREEN_WIDTH as usize * 4)
+            .map_err(|err| err.to_string())?;
+
+        // Black bars around the screen
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+
+        let (width, height) = self.canvas.output_size().map_err(|err| err.to_string())?;
+        let (x, y, width, height) = letterbox(width, height);
+        let destination = FRect::new(x as f32, y as f32, width as f32, height as f32);
+        self.canvas
+            .copy(&self.texture, None, destination)
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Largest area of an output that keeps the Game Boy's aspect ratio,
+/// centered, as x, y, width and height. Whole multiples of the screen are
+/// used when one fits, so every Game Boy pixel is the same size.
+fn letterbox(output_width: u32, output_height: u32) -> (u32, u32, u32, u32) {
+    let scale = (output_width / SCREEN_WIDTH).min(output_height / SCREEN_HEIGHT);
+    let (width, height) = if scale >= 1 {
+        (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale)
+    } else if output_width * SCREEN_HEIGHT < output_height * SCREEN_WIDTH {
+        // Smaller than 1x, fit whichever side is tighter
+        (output_width, output_width * SCREEN_HEIGHT / SCREEN_WIDTH)
+    } else {
+        (output_height * SCREEN_WIDTH / SCREEN_HEIGHT, output_height)
+    };
+    (
+        (output_width - width) / 2,
+        (output_height - height) / 2,
+        width,
+        height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_letterbox() {
+        // Exact multiples fill the output
+        assert_eq!(letterbox(480, 432), (0, 0, 480, 432));
+        // Wider outputs get bars on the sides, at the largest whole scale
+        assert_eq!(letterbox(1920, 1080), (400, 36, 1120, 1008));
+        // Taller outputs get bars on top and bottom
+        assert_eq!(letterbox(500, 1000), (10, 284, 480, 432));
+        // Below 1x the aspect ratio is still kept
+        assert_eq!(letterbox(80, 144), (0, 36, 80, 72));
+    }
+}
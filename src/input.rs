@@ -0,0 +1,406 @@
+use puro_boy::mmu::{Button, MMU};
+use sdl3::event::Event;
+use sdl3::gamepad::{self, Axis, Gamepad};
+use sdl3::keyboard::Keycode;
+use sdl3::GamepadSubsystem;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// How far a stick has to be pushed before it counts as a press
+const AXIS_THRESHOLD: i16 = i16::MAX / 2;
+
+/// Key that starts rebinding every button, one after the other
+const REBIND_KEY: Keycode = Keycode::F1;
+
+/// Host input that can drive a Game Boy button
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(Keycode),
+    GamepadButton(gamepad::Button),
+    // A stick or trigger pushed past the threshold, in the positive or
+    // negative direction
+    GamepadAxis(Axis, bool),
+}
+
+impl Binding {
+    /// Parse "key:Up", "pad:dpup" or "axis:-lefty", using SDL's own names
+    fn parse(text: &str) -> Option<Binding> {
+        let (kind, name) = text.split_once(':')?;
+        match kind {
+            "key" => Keycode::from_name(name).map(Binding::Key),
+            "pad" => gamepad::Button::from_string(name).map(Binding::GamepadButton),
+            "axis" => {
+                let positive = !name.starts_with('-');
+                let name = name.trim_start_matches(['+', '-']);
+                Axis::from_string(name).map(|axis| Binding::GamepadAxis(axis, positive))
+            }
+            _ => None,
+        }
+    }
+
+    fn to_config(self) -> String {
+        match self {
+            Binding::Key(keycode) => format!("key:{}", keycode.name()),
+            Binding::GamepadButton(button) => format!("pad:{}", button.string()),
+            Binding::GamepadAxis(axis, positive) => {
+                format!("axis:{}{}", if positive { '+' } else { '-' }, axis.string())
+            }
+        }
+    }
+}
+
+fn button_name(button: Button) -> &'static str {
+    match button {
+        Button::Right => "right",
+        Button::Left => "left",
+        Button::Up => "up",
+        Button::Down => "down",
+        Button::A => "a",
+        Button::B => "b",
+        Button::Select => "select",
+        Button::Start => "start",
+    }
+}
+
+/// Maps host keys, gamepad buttons and axes to Game Boy buttons
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bindings {
+    map: HashMap<Binding, Button>,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        let defaults = [
+            (Binding::Key(Keycode::Right), Button::Right),
+            (Binding::Key(Keycode::Left), Button::Left),
+            (Binding::Key(Keycode::Up), Button::Up),
+            (Binding::Key(Keycode::Down), Button::Down),
+            (Binding::Key(Keycode::X), Button::A),
+            (Binding::Key(Keycode::Z), Button::B),
+            (Binding::Key(Keycode::Backspace), Button::Select),
+            (Binding::Key(Keycode::Return), Button::Start),
+            (
+                Binding::GamepadButton(gamepad::Button::DPadRight),
+                Button::Right,
+            ),
+            (
+                Binding::GamepadButton(gamepad::Button::DPadLeft),
+                Button::Left,
+            ),
+            (Binding::GamepadButton(gamepad::Button::DPadUp), Button::Up),
+            (
+                Binding::GamepadButton(gamepad::Button::DPadDown),
+                Button::Down,
+            ),
+            (Binding::GamepadButton(gamepad::Button::East), Button::A),
+            (Binding::GamepadButton(gamepad::Button::South), Button::B),
+            (
+                Binding::GamepadButton(gamepad::Button::Back),
+                Button::Select,
+            ),
+            (
+                Binding::GamepadButton(gamepad::Button::Start),
+                Button::Start,
+            ),
+            (Binding::GamepadAxis(Axis::LeftX, true), Button::Right),
+            (Binding::GamepadAxis(Axis::LeftX, false), Button::Left),
+            (Binding::GamepadAxis(Axis::LeftY, false), Button::Up),
+            (Binding::GamepadAxis(Axis::LeftY, true), Button::Down),
+        ];
+        Bindings {
+            map: defaults.into_iter().collect(),
+        }
+    }
+
+    /// Parse a bindings file, one Game Boy button per line:
+    ///
+    /// ```text
+    /// # comment
+    /// up = key:Up, pad:dpup, axis:-lefty
+    /// ```
+    ///
+    /// Buttons missing from the file keep their default bindings.
+    pub fn parse(text: &str) -> Self {
+        let mut bindings = Bindings::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((name, inputs)) = line.split_once('=') else {
+                log::warn!("Bindings line {}: expected `button = inputs`", number + 1);
+                continue;
+            };
+            let name = name.trim().to_lowercase();
+            let Some(button) = Button::ALL.into_iter().find(|b| button_name(*b) == name) else {
+                log::warn!("Bindings line {}: unknown button {:?}", number + 1, name);
+                continue;
+            };
+
+            bindings.clear(button);
+            for input in inputs.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+                match Binding::parse(input) {
+                    Some(binding) => bindings.bind(binding, button),
+                    None => log::warn!("Bindings line {}: unknown input {:?}", number + 1, input),
+                }
+            }
+        }
+        bindings
+    }
+
+    /// Write the bindings back in the format read by `parse`
+    pub fn to_config(&self) -> String {
+        let mut out =
+            String::from("# Game Boy button = key:<name>, pad:<button>, axis:<+|-><axis>\n");
+        for button in Button::ALL {
+            let mut inputs: Vec<String> = self
+                .map
+                .iter()
+                .filter(|(_, b)| **b == button)
+                .map(|(binding, _)| binding.to_config())
+                .collect();
+            inputs.sort();
+            writeln!(out, "{} = {}", button_name(button), inputs.join(", ")).unwrap();
+        }
+        out
+    }
+
+    /// Path of the bindings file, PURO_BOY_BINDINGS or bindings.cfg
+    pub fn path() -> PathBuf {
+        env::var_os("PURO_BOY_BINDINGS")
+            .map_or_else(|| PathBuf::from("bindings.cfg"), PathBuf::from)
+    }
+
+    /// Load the bindings file, falling back to the defaults when it's missing
+    pub fn load() -> Self {
+        match fs::read_to_string(Self::path()) {
+            Ok(text) => Self::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::new(),
+            Err(err) => {
+                log::error!("Couldn't read bindings: {}", err);
+                Self::new()
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(Self::path(), self.to_config())
+    }
+
+    /// Map an input to a button, replacing what the input did before
+    pub fn bind(&mut self, binding: Binding, button: Button) {
+        self.map.insert(binding, button);
+    }
+
+    /// Remove every input mapped to a button
+    pub fn clear(&mut self, button: Button) {
+        self.map.retain(|_, b| *b != button);
+    }
+
+    pub fn get(&self, binding: Binding) -> Option<Button> {
+        self.map.get(&binding).copied()
+    }
+}
+
+/// Turns SDL events into joypad presses, tracks connected gamepads and
+/// runs interactive rebinding
+pub struct Input {
+    bindings: Bindings,
+    gamepad_subsystem: GamepadSubsystem,
+    gamepads: HashMap<u32, Gamepad>,
+    // Axis directions currently pushed past the threshold
+    active_axes: HashMap<(u32, Axis), Option<bool>>,
+    // Buttons still waiting for a new binding while rebinding
+    rebinding: Vec<Button>,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings, gamepad_subsystem: GamepadSubsystem) -> Self {
+        Input {
+            bindings,
+            gamepad_subsystem,
+            gamepads: HashMap::new(),
+            active_axes: HashMap::new(),
+            rebinding: Vec::new(),
+        }
+    }
+
+    /// Rebind every button, the next inputs are assigned in P1 order
+    pub fn start_rebinding(&mut self) {
+        self.rebinding = Button::ALL.into_iter().rev().collect();
+        self.prompt_rebind();
+    }
+
+    fn prompt_rebind(&self) {
+        if let Some(button) = self.rebinding.last() {
+            log::info!("Press the input for {}", button_name(*button));
+        }
+    }
+
+    /// Assign an input to the button being rebound
+    fn rebind(&mut self, binding: Binding) {
+        let Some(button) = self.rebinding.pop() else {
+            return;
+        };
+        self.bindings.clear(button);
+        self.bindings.bind(binding, button);
+        self.prompt_rebind();
+
+        if self.rebinding.is_empty() {
+            match self.bindings.save() {
+                Ok(()) => log::info!("Bindings saved to {}", Bindings::path().display()),
+                Err(err) => log::error!("Couldn't save bindings: {}", err),
+            }
+        }
+    }
+
+    /// Press or release the button bound to an input, or bind it when
+    /// rebinding
+    fn apply(&mut self, binding: Binding, pressed: bool, mmu: &mut MMU) {
+        if !self.rebinding.is_empty() {
+            if pressed {
+                self.rebind(binding);
+            }
+            return;
+        }
+        if let Some(button) = self.bindings.get(binding) {
+            if pressed {
+                mmu.press(button);
+            } else {
+                mmu.release(button);
+            }
+        }
+    }
+
+    /// Handle an input or gamepad hotplug event
+    pub fn handle_event(&mut self, event: &Event, mmu: &mut MMU) {
+        match *event {
+            Event::KeyDown {
+                keycode: Some(REBIND_KEY),
+                repeat: false,
+                ..
+            } => self.start_rebinding(),
+            Event::KeyDown {
+                keycode: Some(keycode),
+                repeat: false,
+                ..
+            } => self.apply(Binding::Key(keycode), true, mmu),
+            Event::KeyUp {
+                keycode: Some(keycode),
+                ..
+            } => self.apply(Binding::Key(keycode), false, mmu),
+            Event::ControllerButtonDown { button, .. } => {
+                self.apply(Binding::GamepadButton(button), true, mmu)
+            }
+            Event::ControllerButtonUp { button, .. } => {
+                self.apply(Binding::GamepadButton(button), false, mmu)
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                // Treat each axis as two buttons, one per direction
+                let direction = if value > AXIS_THRESHOLD {
+                    Some(true)
+                } else if value < -AXIS_THRESHOLD {
+                    Some(false)
+                } else {
+                    None
+                };
+                let previous = self.active_axes.insert((which, axis), direction).flatten();
+                if previous != direction {
+                    if let Some(positive) = previous {
+                        self.apply(Binding::GamepadAxis(axis, positive), false, mmu);
+                    }
+                    if let Some(positive) = direction {
+                        self.apply(Binding::GamepadAxis(axis, positive), true, mmu);
+                    }
+                }
+            }
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.gamepad_subsystem.open(which) {
+                    Ok(gamepad) => {
+                        log::info!("Gamepad connected: {}", gamepad.name().unwrap_or_default());
+                        self.gamepads.insert(which, gamepad);
+                    }
+                    Err(err) => log::warn!("Couldn't open gamepad {}: {}", which, err),
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if self.gamepads.remove(&which).is_some() {
+                    log::info!("Gamepad {} disconnected", which);
+                }
+                self.active_axes.retain(|(id, _), _| *id != which);
+            }
+            _ => {}
+        }
+    }
+
+    /// Drive the rumble motors of connected gamepads from the cartridge
+    pub fn set_rumble(&mut self, on: bool) {
+        let strength = if on { u16::MAX } else { 0 };
+        for gamepad in self.gamepads.values_mut() {
+            // Refreshed whenever the game toggles the motor, so a long
+            // duration is fine
+            if let Err(err) = gamepad.set_rumble(strength, strength, 10_000) {
+                log::debug!("Couldn't rumble gamepad: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut bindings = Bindings::new();
+        bindings.clear(Button::A);
+        bindings.bind(Binding::Key(Keycode::Space), Button::A);
+        bindings.bind(Binding::GamepadButton(gamepad::Button::North), Button::A);
+        assert_eq!(Bindings::parse(&bindings.to_config()), bindings);
+        assert_eq!(
+            Bindings::parse(&Bindings::new().to_config()),
+            Bindings::new()
+        );
+    }
+
+    #[test]
+    fn test_missing_buttons_keep_defaults() {
+        let bindings = Bindings::parse("# only A\na = key:Space\n");
+        assert_eq!(bindings.get(Binding::Key(Keycode::Space)), Some(Button::A));
+        // A's defaults are replaced, everything else is left alone
+        assert_eq!(bindings.get(Binding::Key(Keycode::X)), None);
+        assert_eq!(bindings.get(Binding::Key(Keycode::Z)), Some(Button::B));
+        assert_eq!(bindings.get(Binding::Key(Keycode::Up)), Some(Button::Up));
+    }
+
+    #[test]
+    fn test_unknown_entries_are_skipped() {
+        let bindings = Bindings::parse(
+            "jump = key:Space\nb = key:NotAKey, pad:notabutton, mouse:1, key:S\nnot a binding\n",
+        );
+        assert_eq!(bindings.get(Binding::Key(Keycode::Space)), None);
+        assert_eq!(bindings.get(Binding::Key(Keycode::S)), Some(Button::B));
+        assert_eq!(bindings.get(Binding::Key(Keycode::Z)), None);
+        assert_eq!(bindings.get(Binding::Key(Keycode::X)), Some(Button::A));
+    }
+
+    #[test]
+    fn test_parse_axis_direction() {
+        assert_eq!(
+            Binding::parse("axis:-lefty"),
+            Some(Binding::GamepadAxis(Axis::LeftY, false))
+        );
+        assert_eq!(
+            Binding::parse("axis:+leftx"),
+            Some(Binding::GamepadAxis(Axis::LeftX, true))
+        );
+        assert_eq!(Binding::parse("axis:+notanaxis"), None);
+    }
+}
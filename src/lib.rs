@@ -0,0 +1,6 @@
+pub mod cpu;
+pub mod mmu;
+pub mod save;
+
+pub use cpu::CPU;
+pub use mmu::MMU;
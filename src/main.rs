@@ -1,18 +1,23 @@
-mod cpu;
-mod mmu;
+mod display;
+mod input;
 
-use cpu::CPU;
+use display::{Display, MAX_SCALE, MIN_SCALE};
 use env_logger;
-use mmu::MMU;
+use input::{Bindings, Input};
+use puro_boy::mmu::{CartridgeHeader, Renderer, RtcClock, MMU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use puro_boy::save::SaveFile;
+use puro_boy::CPU;
 use sdl3::event::Event;
 use sdl3::keyboard::Keycode;
 use sdl3::render::WindowCanvas;
-use sdl3::EventPump;
+use sdl3::{EventPump, GamepadSubsystem};
+use std::env;
 use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-fn create_window(width: u32, height: u32) -> (WindowCanvas, EventPump) {
+fn create_window(width: u32, height: u32) -> (WindowCanvas, EventPump, GamepadSubsystem) {
     let sdl_context = sdl3::init().unwrap();
 
     let video_subsystem = sdl_context.video().unwrap();
@@ -20,23 +25,96 @@ fn create_window(width: u32, height: u32) -> (WindowCanvas, EventPump) {
     let window = video_subsystem
         .window("Puro boy", width, height)
         .position_centered()
+        .resizable()
         .build()
         .expect("Couldn't build window");
     let canvas = window.into_canvas();
 
+    // Connected gamepads show up as ControllerDeviceAdded events
+    let gamepad_subsystem = sdl_context
+        .gamepad()
+        .expect("Couldn't initialize gamepad subsystem");
+
     let event_pump = sdl_context
         .event_pump()
         .expect("Couldn't initialize event pump");
 
-    (canvas, event_pump)
+    (canvas, event_pump, gamepad_subsystem)
 }
 
-fn run_emu(rom: Vec<u8>) {
-    // Create a window with Game Boy resolution (160x144)
-    let (mut canvas, mut event_pump) = create_window(160, 144);
+/// Clock used by cartridges with an RTC, PURO_BOY_RTC=emulated ties it to the
+/// emulation speed instead of the host clock
+fn rtc_clock() -> RtcClock {
+    match env::var("PURO_BOY_RTC").as_deref() {
+        Ok("emulated") => RtcClock::Emulated,
+        Ok("host") | Err(_) => RtcClock::Host,
+        Ok(other) => {
+            log::warn!("Unknown PURO_BOY_RTC value {:?}, using host time", other);
+            RtcClock::Host
+        }
+    }
+}
+
+/// PPU renderer, PURO_BOY_RENDERER=fifo trades speed for mid-line raster
+/// effects and accurate mode 3 timing
+fn renderer() -> Renderer {
+    match env::var("PURO_BOY_RENDERER").as_deref() {
+        Ok("fifo") => Renderer::Fifo,
+        Ok("scanline") | Err(_) => Renderer::Scanline,
+        Ok(other) => {
+            log::warn!(
+                "Unknown PURO_BOY_RENDERER value {:?}, using scanline",
+                other
+            );
+            Renderer::Scanline
+        }
+    }
+}
+
+/// Initial window size as a multiple of the screen, PURO_BOY_SCALE=1-8
+fn window_scale() -> u32 {
+    const DEFAULT_SCALE: u32 = 3;
+    match env::var("PURO_BOY_SCALE").map(|scale| scale.parse::<u32>()) {
+        Ok(Ok(scale)) if (MIN_SCALE..=MAX_SCALE).contains(&scale) => scale,
+        Err(_) => DEFAULT_SCALE,
+        Ok(_) => {
+            log::warn!(
+                "PURO_BOY_SCALE should be {}-{}, using {}",
+                MIN_SCALE,
+                MAX_SCALE,
+                DEFAULT_SCALE
+            );
+            DEFAULT_SCALE
+        }
+    }
+}
+
+/// Folder for .sav files, PURO_BOY_SAVE_DIR, or next to the ROM when unset
+fn save_dir() -> Option<PathBuf> {
+    env::var_os("PURO_BOY_SAVE_DIR").map(PathBuf::from)
+}
+
+fn run_emu(rom: Vec<u8>, rom_path: &Path) {
+    // Create a window with Game Boy resolution (160x144), the display
+    // scales it up
+    let (canvas, mut event_pump, gamepad_subsystem) = create_window(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let texture_creator = canvas.texture_creator();
+    let mut display = Display::new(canvas, &texture_creator, window_scale());
+    let mut input = Input::new(Bindings::load(), gamepad_subsystem);
 
     // Initialize MMU with ROM
     let mut mmu = MMU::new(rom);
+    mmu.set_rtc_clock(rtc_clock());
+    mmu.get_ppu_mut().set_renderer(renderer());
+
+    // Restore battery-backed RAM, after the RTC clock is chosen so it can
+    // catch up on the time spent switched off
+    let mut save_file = SaveFile::for_rom(rom_path, save_dir().as_deref(), &mmu);
+    if let Some(save_file) = &mut save_file {
+        if let Err(err) = save_file.load(&mut mmu) {
+            log::error!("Couldn't load save: {}", err);
+        }
+    }
 
     // Initialize CPU
     let mut cpu = CPU::new(&mut mmu);
@@ -57,30 +135,47 @@ fn run_emu(rom: Vec<u8>) {
                 } => {
                     break 'running;
                 }
-                _ => {}
+                event => {
+                    if !display.handle_event(&event) {
+                        input.handle_event(&event, cpu.memory);
+                    }
+                }
             }
         }
 
         // Execute a number of CPU cycles
-        // A single frame is ~70,000 cycles at 4MHz
-        let cycles_per_frame = 70000;
+        // A single frame is 70,224 cycles at 4MHz
+        let cycles_per_frame = 70224;
         let mut cycles_this_frame = 0;
 
         while cycles_this_frame < cycles_per_frame {
             // Execute one CPU instruction
-            cpu.step();
-
-            // Add the cycles for this instruction (for simplicity, using 4 cycles)
-            cycles_this_frame += 4;
+            let cycles = match cpu.step() {
+                Ok(cycles) => cycles,
+                Err(err) => {
+                    log::error!("{}", err);
+                    break 'running;
+                }
+            };
+            cycles_this_frame += cycles;
 
-            // Update the PPU
-            if cpu.memory.update_ppu(4) {
-                // If a frame is ready, render it
-                cpu.memory.get_ppu_mut().render(&mut canvas);
+            // Clock the PPU and the other peripherals by the same amount
+            if cpu.memory.tick(cycles) {
+                // If a frame is ready, show it
+                display.present(cpu.memory);
             }
         }
 
-        canvas.clear();
+        if let Some(motor_on) = cpu.memory.take_rumble_event() {
+            log::debug!("Rumble motor {}", if motor_on { "on" } else { "off" });
+            input.set_rumble(motor_on);
+        }
+
+        if let Some(save_file) = &mut save_file {
+            if let Err(err) = save_file.save_periodically(cpu.memory) {
+                log::error!("Couldn't write save: {}", err);
+            }
+        }
 
         // Limit to ~60 FPS
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
@@ -88,6 +183,12 @@ fn run_emu(rom: Vec<u8>) {
         // Print CPU registers for debugging
         cpu.print_registers();
     }
+
+    if let Some(save_file) = &mut save_file {
+        if let Err(err) = save_file.save(cpu.memory) {
+            log::error!("Couldn't write save: {}", err);
+        }
+    }
 }
 
 pub fn main() {
@@ -110,6 +211,25 @@ pub fn main() {
 
     log::debug!("Loaded ROM: {} ({} bytes)", rom_path, rom.len());
 
+    let header = match CartridgeHeader::parse(&rom) {
+        Ok(header) => header,
+        Err(err) => {
+            eprintln!("Invalid ROM file: {}", err);
+            return;
+        }
+    };
+    log::info!(
+        "Cartridge: {} ({:?}, {} KiB ROM, {} KiB RAM)",
+        header.title,
+        header.mapper(),
+        header.rom_size() / 1024,
+        header.ram_size() / 1024
+    );
+    // Plenty of homebrew ships with bad checksums, so only warn about them
+    if let Err(err) = header.validate(&rom) {
+        log::warn!("{}", err);
+    }
+
     // Run the emulator with the loaded ROM
-    run_emu(rom);
+    run_emu(rom, Path::new(rom_path));
 }
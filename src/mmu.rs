@@ -1,128 +1,138 @@
 mod cart;
+mod interrupt;
 mod ioreg;
+mod joypad;
 mod ppu;
+mod timer;
 
+pub use cart::{Cartridge, CartridgeHeader, RtcClock};
+use cart::{CgbSupport, HEADER_END, ROM_BANK_SIZE};
+pub use interrupt::Interrupt;
+use interrupt::InterruptController;
+pub use joypad::Button;
+use joypad::Joypad;
 use ppu::PPU;
+pub use ppu::{Renderer, FRAMEBUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use timer::Timer;
 
-const ROM_BANK_SIZE: usize = 0x4000;
-const VRAM_SIZE: usize = 0x2000;
-const EXTERNAL_RAM_SIZE: usize = 0x2000;
 const WRAM_SIZE: usize = 0x2000;
-const ECHO_RAM_SIZE: usize = 0x1E00;
-const OAM_SIZE: usize = 0xA0;
 const IO_REGISTERS_SIZE: usize = 0x80;
 const HRAM_SIZE: usize = 0x7F;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct MMU {
     // Memory Map
-    // ROM Bank 0 (0x0000-0x3FFF)
-    rom_bank0: [u8; ROM_BANK_SIZE],
-    // ROM Bank 1-n (0x4000-0x7FFF)
-    rom_bank1: [u8; ROM_BANK_SIZE],
-
-    // External RAM (0xA000-0xBFFF)
-    ext_ram: [u8; EXTERNAL_RAM_SIZE],
-    // Work RAM (0xC000-0xDFFF)
+    // Cartridge ROM (0x0000-0x7FFF) and External RAM (0xA000-0xBFFF),
+    // banked by the memory bank controller
+    cartridge: Box<dyn Cartridge>,
+    // Whether the cartridge RAM survives power-off
+    battery: bool,
+    // Work RAM (0xC000-0xDFFF), mirrored by Echo RAM (0xE000-0xFDFF)
     wram: [u8; WRAM_SIZE],
-    // Echo RAM - mirror of WRAM (0xE000-0xFDFF)
-    eram: [u8; ECHO_RAM_SIZE],
-    // OAM - Sprite Attribute Table (0xFE00-0xFE9F)
-    oam: [u8; OAM_SIZE],
     // I/O Registers (0xFF00-0xFF7F)
     io_registers: [u8; IO_REGISTERS_SIZE],
     // High RAM (0xFF80-0xFFFE)
     hram: [u8; HRAM_SIZE],
-    // Interrupt Enable Register (0xFFFF)
-    ie_register: u8,
+    // Interrupt Flag (0xFF0F) and Interrupt Enable (0xFFFF) registers
+    interrupts: InterruptController,
     // PPU
     pub ppu: PPU,
+    // Timer and divider (0xFF04-0xFF07)
+    timer: Timer,
+    // Joypad (0xFF00)
+    joypad: Joypad,
+
+    // CGB-only state
+    cgb_mode: bool,
+    // KEY1 (0xFF4D) - bit 0 arms a speed switch, bit 7 is the current speed
+    speed_switch_armed: bool,
+    double_speed: bool,
 }
 
 impl MMU {
-    pub fn new(rom: Vec<u8>) -> MMU {
-        let mut rom_bank0 = [0; ROM_BANK_SIZE];
-        let mut rom_bank1 = [0; ROM_BANK_SIZE];
-
-        // Copy ROM data to ROM banks
-        for i in 0..ROM_BANK_SIZE {
-            if i < rom.len() {
-                rom_bank0[i] = rom[i];
-            }
+    pub fn new(mut rom: Vec<u8>) -> MMU {
+        // Pad small images so both ROM banks are always mapped
+        if rom.len() < 2 * ROM_BANK_SIZE {
+            rom.resize(2 * ROM_BANK_SIZE, 0);
         }
+        Self::with_cartridge(cart::load_cartridge(rom, RtcClock::Host))
+    }
 
-        for i in 0..ROM_BANK_SIZE {
-            let index = i + ROM_BANK_SIZE;
-            if index < rom.len() {
-                rom_bank1[i] = rom[index];
-            }
-        }
+    /// Build the memory map around any cartridge, including custom mappers
+    pub fn with_cartridge(cartridge: Box<dyn Cartridge>) -> MMU {
+        let header_bytes: Vec<u8> = (0..HEADER_END as u16)
+            .map(|address| cartridge.read_rom(address))
+            .collect();
+        let header = CartridgeHeader::parse(&header_bytes).ok();
+        let cgb_mode = header
+            .as_ref()
+            .is_some_and(|header| header.cgb_support != CgbSupport::None);
 
-        let mut mmu = MMU {
-            rom_bank0,
-            rom_bank1,
+        let mut ppu = PPU::new();
+        ppu.set_cgb_mode(cgb_mode);
 
-            ext_ram: [0; EXTERNAL_RAM_SIZE],
+        MMU {
+            cartridge,
+            battery: header.as_ref().is_some_and(|header| header.has_battery()),
             wram: [0; WRAM_SIZE],
-            eram: [0; ECHO_RAM_SIZE],
-            oam: [0; OAM_SIZE],
             io_registers: [0; IO_REGISTERS_SIZE],
             hram: [0; HRAM_SIZE],
-            ie_register: 0,
-            ppu: PPU::new(),
-        };
-
-        // Initialize PPU with tile data from ROM if it exists
-        mmu.init_ppu();
-
-        mmu
-    }
-
-    /// Initialize PPU with tile data from ROM
-    fn init_ppu(&mut self) {
-        // Pass relevant tile data to PPU
-        self.ppu.init(&self.rom_bank0, &self.rom_bank1);
+            interrupts: InterruptController::new(),
+            ppu,
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            cgb_mode,
+            speed_switch_armed: false,
+            double_speed: false,
+        }
     }
 
     /// Reads a byte from memory at the specified address.
     pub fn read(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3FFF => self.rom_bank0[address as usize],
-            0x4000..=0x7FFF => self.rom_bank1[(address - 0x4000) as usize],
+            0x0000..=0x7FFF => self.cartridge.read_rom(address),
+            // VRAM and OAM read 0xFF while the PPU is using them
+            0x8000..=0x9FFF if !self.ppu.vram_accessible() => 0xFF,
             0x8000..=0x9FFF => {
                 let vram_addr = (address - 0x8000) as usize;
                 self.ppu.vram[vram_addr]
             }
-            0xA000..=0xBFFF => self.ext_ram[(address - 0xA000) as usize],
+            0xA000..=0xBFFF => self.cartridge.read_ram(address),
             0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize],
             0xE000..=0xFDFF => self.wram[(address - 0xE000) as usize], // Echo RAM, mirrors WRAM
-            0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize],
+            // OAM - Sprite Attribute Table, owned by the PPU
+            0xFE00..=0xFE9F if !self.ppu.oam_accessible() => 0xFF,
+            0xFE00..=0xFE9F => self.ppu.oam[(address - 0xFE00) as usize],
             0xFEA0..=0xFEFF => 0, // Unusable memory
-            0xFF00..=0xFF7F => self.io_registers[(address - 0xFF00) as usize],
+            0xFF00..=0xFF7F => match address {
+                0xFF00 => self.joypad.read(),
+                0xFF04..=0xFF07 => self.timer.read(address),
+                0xFF0F => self.interrupts.read_flag(),
+                0xFF41 => self.ppu.read_lcd_status(),
+                0xFF44 => self.ppu.get_ly(),
+                0xFF4D => self.read_key1(),
+                _ => self.io_registers[(address - 0xFF00) as usize],
+            },
             0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize],
-            0xFFFF => self.ie_register,
+            0xFFFF => self.interrupts.read_enable(),
         }
     }
 
     /// Writes a byte to memory at the specified address.
     pub fn write(&mut self, address: u16, value: u8) {
         match address {
-            0x0000..=0x7FFF => {
-                // ROM is read-only, but some games use writes to this region for memory bank switching
-                // For now, we ignore these writes
-            }
-            0x8000..=0x9FFF => {
-                let vram_addr = (address - 0x8000) as usize;
-                self.ppu.vram[vram_addr] = value;
-                // Update tile data in PPU if VRAM is written to
-                if address >= 0x8000 && address < 0x9800 {
-                    self.ppu.update_tile(address, value);
-                }
-            }
-            0xA000..=0xBFFF => self.ext_ram[(address - 0xA000) as usize] = value,
+            // ROM is read-only, writes go to the memory bank controller
+            0x0000..=0x7FFF => self.cartridge.write_rom(address, value),
+            // Writes to VRAM and OAM are dropped while the PPU is using them
+            0x8000..=0x9FFF if !self.ppu.vram_accessible() => {}
+            // Tile data also goes to the PPU's decoded tile cache
+            0x8000..=0x97FF => self.ppu.update_tile(address, value),
+            0x9800..=0x9FFF => self.ppu.vram[(address - 0x8000) as usize] = value,
+            0xA000..=0xBFFF => self.cartridge.write_ram(address, value),
             0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize] = value,
             0xE000..=0xFDFF => self.wram[(address - 0xE000) as usize] = value, // Echo RAM, mirrors WRAM
-            0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize] = value,
+            0xFE00..=0xFE9F if !self.ppu.oam_accessible() => {}
+            0xFE00..=0xFE9F => self.ppu.oam[(address - 0xFE00) as usize] = value,
             0xFEA0..=0xFEFF => {} // Unusable memory, ignore writes
             0xFF00..=0xFF7F => {
                 self.io_registers[(address - 0xFF00) as usize] = value;
@@ -139,33 +149,35 @@ impl MMU {
                     0xFF49 => self.ppu.set_obj_palette1(value),
                     0xFF4A => self.ppu.set_window_y(value),
                     0xFF4B => self.ppu.set_window_x(value),
+                    0xFF00 => self.joypad.write(value),
+                    0xFF04..=0xFF07 => self.timer.write(address, value),
+                    0xFF0F => self.interrupts.write_flag(value),
+                    0xFF4D => self.speed_switch_armed = self.cgb_mode && value & 0x01 != 0,
                     _ => {}
                 }
             }
             0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize] = value,
-            0xFFFF => self.ie_register = value,
+            0xFFFF => self.interrupts.write_enable(value),
         }
     }
 
     /// Reads a word (2 bytes) from memory at the specified address.
     pub fn read_word(&self, address: u16) -> u16 {
         let low = self.read(address);
-        let high = self.read(address + 1);
+        let high = self.read(address.wrapping_add(1));
         ((high as u16) << 8) | (low as u16)
     }
 
     /// Writes a word (2 bytes) to memory at the specified address.
     pub fn write_word(&mut self, address: u16, value: u16) {
         self.write(address, (value & 0x00FF) as u8);
-        self.write(address + 1, ((value >> 8) & 0x00FF) as u8);
+        self.write(address.wrapping_add(1), ((value >> 8) & 0x00FF) as u8);
     }
 
     /// Reads a byte from the ROM at the specified address.
     pub fn read_rom(&self, address: u16) -> u8 {
-        if address < 0x4000 {
-            self.rom_bank0[address as usize]
-        } else if address < 0x8000 {
-            self.rom_bank1[(address - 0x4000) as usize]
+        if address < 0x8000 {
+            self.cartridge.read_rom(address)
         } else {
             0 // Invalid ROM address
         }
@@ -185,4 +197,185 @@ impl MMU {
     pub fn update_ppu(&mut self, cycles: u32) -> bool {
         self.ppu.update(cycles)
     }
+
+    /// Advance every peripheral by the number of T-cycles the CPU just used
+    /// Returns true if a frame is ready to be rendered
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        // The PPU keeps its speed when the CPU runs at double speed
+        let ppu_cycles = if self.double_speed {
+            cycles / 2
+        } else {
+            cycles
+        };
+        let frame_ready = self.update_ppu(ppu_cycles);
+        // The cartridge RTC has its own crystal, so it doesn't speed up either
+        self.cartridge.tick(ppu_cycles);
+        // The timer runs off the CPU clock, so it does speed up
+        self.timer.tick(cycles);
+
+        let requests = self.ppu.take_interrupts()
+            | self.timer.take_interrupts()
+            | self.joypad.take_interrupts();
+        self.interrupts.request_bits(requests);
+        frame_ready
+    }
+
+    /// Whether the cartridge has battery-backed RAM worth saving
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /// Cartridge RAM, plus RTC state on MBC3, in the usual .sav layout
+    pub fn save_data(&self) -> Vec<u8> {
+        self.cartridge.save_data()
+    }
+
+    /// Restore cartridge RAM from a .sav file
+    pub fn load_data(&mut self, data: &[u8]) {
+        self.cartridge.load_data(data);
+    }
+
+    /// Hold down a button, called by the front-end on input events
+    pub fn press(&mut self, button: Button) {
+        self.joypad.press(button);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    /// Choose whether the cartridge RTC follows emulated or host time
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.cartridge.set_rtc_clock(clock);
+    }
+
+    /// Motor state of rumble cartridges, `Some` only when it changed since
+    /// the last call so the front-end can start or stop force feedback
+    pub fn take_rumble_event(&mut self) -> Option<bool> {
+        self.cartridge.take_rumble_event()
+    }
+
+    /// Read KEY1 (0xFF4D), which only exists on the CGB
+    fn read_key1(&self) -> u8 {
+        if !self.cgb_mode {
+            return 0xFF;
+        }
+        ((self.double_speed as u8) << 7) | 0x7E | self.speed_switch_armed as u8
+    }
+
+    /// Whether STOP should switch the CPU speed instead of entering low-power mode
+    pub fn speed_switch_requested(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    /// Enter STOP's low-power mode, which resets the divider
+    pub fn enter_stop(&mut self) {
+        self.timer.write(0xFF04, 0);
+        // Only presses from now on end STOP
+        self.joypad.take_wake();
+    }
+
+    /// Whether a joypad line went low since STOP was entered
+    pub fn joypad_woke(&mut self) -> bool {
+        self.joypad.take_wake()
+    }
+
+    /// Toggle between normal and double speed mode
+    pub fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        // STOP resets the divider
+        self.timer.write(0xFF04, 0);
+    }
+
+    /// Raise an interrupt by setting its bit in IF
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.request(interrupt);
+    }
+
+    /// Highest priority interrupt that is requested and enabled, if any
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        self.interrupts.pending()
+    }
+
+    /// Clear the IF bit of an interrupt the CPU is about to service
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.acknowledge(interrupt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vram_and_oam_locking() {
+        let mut mmu = MMU::new(vec![0; 0x8000]);
+        // Everything is accessible while the LCD is off
+        mmu.write(0x8010, 0x5A);
+        mmu.write(0xFE00, 0x12);
+
+        // OAM scan: OAM is locked, VRAM isn't
+        mmu.write(0xFF40, 0x80);
+        assert_eq!(mmu.read(0xFE00), 0xFF);
+        mmu.write(0xFE00, 0x34);
+        assert_eq!(mmu.read(0x8010), 0x5A);
+
+        // Drawing: VRAM is locked too, and tile writes don't reach the cache
+        mmu.tick(80);
+        assert_eq!(mmu.read(0x8010), 0xFF);
+        mmu.write(0x8000, 0xFF);
+        assert_eq!(mmu.ppu.tiles[0].data[0], [0; 8]);
+        assert_eq!(mmu.ppu.tiles[1].data[0], [0, 1, 0, 1, 1, 0, 1, 0]);
+
+        // HBlank: both are open again
+        mmu.tick(172);
+        assert_eq!(mmu.read(0x8010), 0x5A);
+        assert_eq!(mmu.read(0xFE00), 0x12);
+        mmu.write(0xFE00, 0x56);
+        assert_eq!(mmu.read(0xFE00), 0x56);
+
+        mmu.write(0xFF40, 0x00);
+        assert_eq!(mmu.read(0x8000), 0x00);
+    }
+
+    /// CGB-enhanced ROM, so KEY1 is present
+    fn cgb_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        rom
+    }
+
+    #[test]
+    fn test_key1() {
+        // No KEY1 on DMG
+        let mut mmu = MMU::new(vec![0; 0x8000]);
+        mmu.write(0xFF4D, 0x01);
+        assert_eq!(mmu.read(0xFF4D), 0xFF);
+        assert!(!mmu.speed_switch_requested());
+
+        let mut mmu = MMU::new(cgb_rom());
+        assert_eq!(mmu.read(0xFF4D), 0x7E);
+        mmu.write(0xFF4D, 0x01);
+        assert_eq!(mmu.read(0xFF4D), 0x7F);
+        assert!(mmu.speed_switch_requested());
+
+        mmu.switch_speed();
+        assert_eq!(mmu.read(0xFF4D), 0xFE);
+        assert!(!mmu.speed_switch_requested());
+    }
+
+    #[test]
+    fn test_double_speed_ppu() {
+        let mut mmu = MMU::new(cgb_rom());
+        mmu.write(0xFF4D, 0x01);
+        mmu.switch_speed();
+        mmu.write(0xFF40, 0x80);
+
+        // The 80 dots of OAM scan take 160 T-cycles at double speed
+        mmu.tick(156);
+        assert_eq!(mmu.read(0xFF41) & 0x03, 2);
+        mmu.tick(4);
+        assert_eq!(mmu.read(0xFF41) & 0x03, 3);
+    }
 }
@@ -0,0 +1,466 @@
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rom_only;
+mod rtc;
+
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use rom_only::RomOnly;
+pub use rtc::RtcClock;
+use std::fmt;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+// Header layout (0x0100-0x014F)
+const LOGO_START: usize = 0x104;
+const TITLE_START: usize = 0x134;
+const MANUFACTURER_START: usize = 0x13F;
+const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE_START: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+const CARTRIDGE_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+const RAM_SIZE: usize = 0x149;
+const DESTINATION: usize = 0x14A;
+const OLD_LICENSEE: usize = 0x14B;
+const VERSION: usize = 0x14C;
+const HEADER_CHECKSUM: usize = 0x14D;
+const GLOBAL_CHECKSUM: usize = 0x14E;
+pub const HEADER_END: usize = 0x150;
+
+/// Bitmap checked by the boot ROM before starting the game
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    // The image is too small to hold a header
+    TooSmall(usize),
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    LogoMismatch,
+    HeaderChecksum { expected: u8, computed: u8 },
+    GlobalChecksum { expected: u16, computed: u16 },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::TooSmall(len) => write!(
+                f,
+                "ROM is {} bytes, too small to contain a cartridge header",
+                len
+            ),
+            CartridgeError::InvalidRomSize(code) => {
+                write!(f, "Unknown ROM size code 0x{:02X}", code)
+            }
+            CartridgeError::InvalidRamSize(code) => {
+                write!(f, "Unknown RAM size code 0x{:02X}", code)
+            }
+            CartridgeError::LogoMismatch => write!(f, "Nintendo logo in the header is corrupted"),
+            CartridgeError::HeaderChecksum { expected, computed } => write!(
+                f,
+                "Header checksum mismatch: header says 0x{:02X}, computed 0x{:02X}",
+                expected, computed
+            ),
+            CartridgeError::GlobalChecksum { expected, computed } => write!(
+                f,
+                "Global checksum mismatch: header says 0x{:04X}, computed 0x{:04X}",
+                expected, computed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    // DMG only
+    None,
+    // Works on both DMG and CGB (0x80)
+    Enhanced,
+    // CGB only (0xC0)
+    Only,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// Memory bank controller, from the cartridge type byte (0x147)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    // Only present on later CGB cartridges
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub new_licensee_code: String,
+    pub sgb_support: bool,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /// Parse the header at 0x0100-0x014F of a ROM image
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb_support = match rom[CGB_FLAG] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // CGB cartridges shortened the title to make room for the CGB flag,
+        // and later ones for the manufacturer code too
+        let (title, manufacturer_code) = if cgb_support == CgbSupport::None {
+            (read_string(&rom[TITLE_START..CGB_FLAG + 1]), None)
+        } else {
+            let code = &rom[MANUFACTURER_START..CGB_FLAG];
+            if code.iter().all(|b| b.is_ascii_alphanumeric()) {
+                (
+                    read_string(&rom[TITLE_START..MANUFACTURER_START]),
+                    Some(read_string(code)),
+                )
+            } else {
+                (read_string(&rom[TITLE_START..CGB_FLAG]), None)
+            }
+        };
+
+        let rom_size_code = rom[ROM_SIZE];
+        if rom_size_code > 0x08 {
+            return Err(CartridgeError::InvalidRomSize(rom_size_code));
+        }
+        let ram_size_code = rom[RAM_SIZE];
+        if ram_size_code > 0x05 {
+            return Err(CartridgeError::InvalidRamSize(ram_size_code));
+        }
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb_support,
+            new_licensee_code: read_string(&rom[NEW_LICENSEE_START..SGB_FLAG]),
+            sgb_support: rom[SGB_FLAG] == 0x03,
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size_code,
+            ram_size_code,
+            destination: if rom[DESTINATION] == 0x00 {
+                Destination::Japan
+            } else {
+                Destination::Overseas
+            },
+            old_licensee_code: rom[OLD_LICENSEE],
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: ((rom[GLOBAL_CHECKSUM] as u16) << 8) | rom[GLOBAL_CHECKSUM + 1] as u16,
+        })
+    }
+
+    /// Check the Nintendo logo, the header checksum and the global checksum
+    pub fn validate(&self, rom: &[u8]) -> Result<(), CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        if rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()] != NINTENDO_LOGO {
+            return Err(CartridgeError::LogoMismatch);
+        }
+
+        let computed = header_checksum(rom);
+        if computed != self.header_checksum {
+            return Err(CartridgeError::HeaderChecksum {
+                expected: self.header_checksum,
+                computed,
+            });
+        }
+
+        let computed = global_checksum(rom);
+        if computed != self.global_checksum {
+            return Err(CartridgeError::GlobalChecksum {
+                expected: self.global_checksum,
+                computed,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn mapper(&self) -> Mapper {
+        match self.cartridge_type {
+            0x00 | 0x08 | 0x09 => Mapper::RomOnly,
+            0x01..=0x03 => Mapper::Mbc1,
+            0x05 | 0x06 => Mapper::Mbc2,
+            0x0B..=0x0D => Mapper::Mmm01,
+            0x0F..=0x13 => Mapper::Mbc3,
+            0x19..=0x1E => Mapper::Mbc5,
+            0x20 => Mapper::Mbc6,
+            0x22 => Mapper::Mbc7,
+            0xFC => Mapper::PocketCamera,
+            0xFD => Mapper::Tama5,
+            0xFE => Mapper::HuC3,
+            0xFF => Mapper::HuC1,
+            other => Mapper::Unknown(other),
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        )
+    }
+
+    pub fn has_timer(&self) -> bool {
+        matches!(self.cartridge_type, 0x0F | 0x10)
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        matches!(self.cartridge_type, 0x1C..=0x1E)
+    }
+
+    /// ROM size in bytes, 32 KiB << code
+    pub fn rom_size(&self) -> usize {
+        0x8000 << self.rom_size_code
+    }
+
+    /// External RAM size in bytes
+    pub fn ram_size(&self) -> usize {
+        match self.ram_size_code {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+}
+
+/// Cartridge hardware, mapped at 0x0000-0x7FFF and 0xA000-0xBFFF.
+/// Implement this to plug in a mapper the emulator doesn't know about.
+pub trait Cartridge: fmt::Debug {
+    fn read_rom(&self, address: u16) -> u8;
+
+    /// Writes to the ROM area go to the controller's registers
+    fn write_rom(&mut self, address: u16, value: u8);
+
+    fn read_ram(&self, address: u16) -> u8;
+
+    fn write_ram(&mut self, address: u16, value: u8);
+
+    /// Battery-backed state to persist in a .sav file
+    fn save_data(&self) -> Vec<u8>;
+
+    /// Restore state produced by `save_data`
+    fn load_data(&mut self, data: &[u8]);
+
+    /// Clock the cartridge hardware, at normal speed even in CGB double speed
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// Choose where the RTC gets its time from, for cartridges with a clock
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    /// New rumble motor state, if the game changed it since the last call
+    fn take_rumble_event(&mut self) -> Option<bool> {
+        None
+    }
+}
+
+/// Build the cartridge for a ROM image, picking the memory bank controller
+/// named in its header. ROMs without a valid header are treated as ROM-only.
+pub fn load_cartridge(rom: Vec<u8>, rtc_clock: RtcClock) -> Box<dyn Cartridge> {
+    let header = CartridgeHeader::parse(&rom).ok();
+    let header = header.as_ref();
+    match header.map(|h| h.mapper()) {
+        Some(Mapper::Mbc1) => Box::new(Mbc1::new(rom, header)),
+        Some(Mapper::Mbc2) => Box::new(Mbc2::new(rom)),
+        Some(Mapper::Mbc3) => Box::new(Mbc3::new(rom, header, rtc_clock)),
+        Some(Mapper::Mbc5) => Box::new(Mbc5::new(rom, header)),
+        Some(Mapper::RomOnly) | None => Box::new(RomOnly::new(rom, header)),
+        Some(other) => {
+            log::warn!("Unsupported mapper {:?}, running as ROM-only", other);
+            Box::new(RomOnly::new(rom, header))
+        }
+    }
+}
+
+/// Read from a switchable ROM bank. Bank numbers wrap around the ROM size,
+/// which is a power of 2, and reads past the end of the image give 0xFF.
+fn banked_rom_read(rom: &[u8], bank: usize, address: u16) -> u8 {
+    let banks = (rom.len() / ROM_BANK_SIZE).max(1);
+    let bank = bank & (banks.next_power_of_two() - 1);
+    rom.get(bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1)))
+        .copied()
+        .unwrap_or(0xFF)
+}
+
+/// Read an ASCII string padded with 0x00
+fn read_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '?'
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Checksum over 0x0134-0x014C, as computed by the boot ROM
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_START..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// Sum of every byte in the ROM except the global checksum itself
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != GLOBAL_CHECKSUM && *i != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+/// ROM where each bank starts with its bank number, low byte first, for
+/// the mapper tests
+#[cfg(test)]
+fn test_rom(banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for bank in 0..banks {
+        rom[bank * ROM_BANK_SIZE] = bank as u8;
+        rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+    }
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a 32 KiB ROM with a valid header
+    fn make_rom(title: &str, cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO_START..LOGO_START + 48].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_START..TITLE_START + title.len()].copy_from_slice(title.as_bytes());
+        rom[CARTRIDGE_TYPE] = cartridge_type;
+        rom[RAM_SIZE] = 0x02;
+        rom[DESTINATION] = 0x01;
+        rom[HEADER_CHECKSUM] = header_checksum(&rom);
+        let global = global_checksum(&rom);
+        rom[GLOBAL_CHECKSUM] = (global >> 8) as u8;
+        rom[GLOBAL_CHECKSUM + 1] = global as u8;
+        rom
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let rom = make_rom("PUROTEST", 0x03);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "PUROTEST");
+        assert_eq!(header.mapper(), Mapper::Mbc1);
+        assert!(header.has_battery());
+        assert_eq!(header.rom_size(), 0x8000);
+        assert_eq!(header.ram_size(), 0x2000);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.validate(&rom), Ok(()));
+    }
+
+    #[test]
+    fn test_cgb_title() {
+        // Early CGB cartridges use 15 characters for the title
+        let mut rom = make_rom("CGB TITLE 15 CH", 0x00);
+        rom[CGB_FLAG] = 0x80;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+        assert_eq!(header.title, "CGB TITLE 15 CH");
+        assert_eq!(header.manufacturer_code, None);
+
+        // Later ones end it at the manufacturer code
+        let mut rom = make_rom("CGBTITLE\0\0\0AXYZ", 0x00);
+        rom[CGB_FLAG] = 0xC0;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "CGBTITLE");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AXYZ"));
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let mut rom = make_rom("PUROTEST", 0x00);
+        rom[0x7FFF] = 0x01;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(matches!(
+            header.validate(&rom),
+            Err(CartridgeError::GlobalChecksum { .. })
+        ));
+
+        rom[TITLE_START] = b'Q';
+        assert!(matches!(
+            header.validate(&rom),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+
+        rom[LOGO_START] = 0;
+        assert_eq!(header.validate(&rom), Err(CartridgeError::LogoMismatch));
+
+        assert_eq!(
+            CartridgeHeader::parse(&rom[..0x100]),
+            Err(CartridgeError::TooSmall(0x100))
+        );
+    }
+
+    #[test]
+    fn test_load_cartridge_picks_mapper() {
+        let ram_after_write = |cartridge_type: u8| {
+            let mut cartridge = load_cartridge(make_rom("MAPPER", cartridge_type), RtcClock::Host);
+            cartridge.write_rom(0x0000, 0x0A);
+            cartridge.write_ram(0xA000, 0xAB);
+            cartridge.read_ram(0xA000)
+        };
+        assert_eq!(ram_after_write(0x03), 0xAB);
+        // MBC2 only stores the lower nibble
+        assert_eq!(ram_after_write(0x06), 0xFB);
+        assert_eq!(ram_after_write(0x1B), 0xAB);
+    }
+}
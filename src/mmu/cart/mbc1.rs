@@ -0,0 +1,198 @@
+use super::{
+    banked_rom_read, Cartridge, CartridgeHeader, NINTENDO_LOGO, RAM_BANK_SIZE, ROM_BANK_SIZE,
+};
+
+/// MBC1, up to 2 MiB of ROM and 32 KiB of RAM
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    // 0x0000-0x1FFF, 0x0A in the lower nibble enables RAM
+    ram_enabled: bool,
+    // 0x2000-0x3FFF, lower 5 bits of the ROM bank
+    bank1: u8,
+    // 0x4000-0x5FFF, upper ROM bank bits or RAM bank
+    bank2: u8,
+    // 0x6000-0x7FFF, mode 1 applies bank2 to 0x0000-0x3FFF and RAM too
+    advanced_mode: bool,
+
+    // MBC1M multicarts only wire 4 bits of bank1
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, header: Option<&CartridgeHeader>) -> Self {
+        let ram_size = header.map_or(0, |h| h.ram_size());
+        let multicart = is_multicart(&rom);
+        if multicart {
+            log::info!("Detected MBC1M multicart");
+        }
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_mode: false,
+            multicart,
+        }
+    }
+
+    /// Number of bits bank1 contributes to the ROM bank
+    fn bank1_bits(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let bank = if self.advanced_mode {
+            self.bank2 as usize
+        } else {
+            0
+        };
+        (bank * RAM_BANK_SIZE + (address - 0xA000) as usize) & (self.ram.len() - 1)
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF if self.advanced_mode => (self.bank2 as usize) << self.bank1_bits(),
+            0x0000..=0x3FFF => 0,
+            _ => {
+                let low = self.bank1 & ((1 << self.bank1_bits()) - 1);
+                ((self.bank2 as usize) << self.bank1_bits()) | low as usize
+            }
+        };
+        banked_rom_read(&self.rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // Bank 0 can't be selected here, it's read as bank 1
+                // (the check uses all 5 bits, even on multicarts)
+                self.bank1 = value & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.advanced_mode = value & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(address);
+        self.ram[offset] = value;
+    }
+
+    /// Contents of the battery-backed RAM
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/// MBC1M carts are 1 MiB images made of several games, each with its own
+/// header. The second game starts at bank 0x10.
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x100000 {
+        return false;
+    }
+    let logo = 0x10 * ROM_BANK_SIZE + 0x104;
+    rom[logo..logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_rom;
+    use super::*;
+
+    fn make_mbc1(banks: usize, ram_size: usize) -> Mbc1 {
+        let mut mbc = Mbc1::new(test_rom(banks), None);
+        mbc.ram = vec![0; ram_size];
+        mbc
+    }
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = make_mbc1(128, 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+
+        // Selecting bank 0 gives bank 1
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // Bank 0x20 can't be reached, 0x21 is mapped instead
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+
+        // In mode 1, bank2 also applies to 0x0000-0x3FFF
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn test_bank_wraps_to_rom_size() {
+        let mut mbc = make_mbc1(4, 0);
+        mbc.write_rom(0x2000, 0x06);
+        assert_eq!(mbc.read_rom(0x4000), 2);
+    }
+
+    #[test]
+    fn test_ram_enable_and_banking() {
+        let mut mbc = make_mbc1(4, 0x8000);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA000, 0x34);
+        assert_eq!(mbc.read_ram(0xA000), 0x34);
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        assert_eq!(mbc.ram[2 * RAM_BANK_SIZE], 0x34);
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut rom = test_rom(64);
+        let logo = 0x10 * ROM_BANK_SIZE + 0x104;
+        rom[logo..logo + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        let mut mbc = Mbc1::new(rom, None);
+        assert!(mbc.multicart);
+
+        // bank2 selects the game, only 4 bits of bank1 are used
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+    }
+}
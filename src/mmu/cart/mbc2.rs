@@ -0,0 +1,115 @@
+use super::{banked_rom_read, Cartridge};
+
+// 512 half-bytes of RAM built into the controller
+const MBC2_RAM_SIZE: usize = 0x200;
+
+/// MBC2, up to 256 KiB of ROM and 512x4 bits of built-in RAM
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    // Only the lower nibble of each byte is stored
+    ram: Vec<u8>,
+
+    ram_enabled: bool,
+    // 4-bit ROM bank for 0x4000-0x7FFF
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: vec![0; MBC2_RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        banked_rom_read(&self.rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        // Both registers live in 0x0000-0x3FFF, address bit 8 picks which one
+        match address {
+            0x0000..=0x3FFF if address & 0x100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => {
+                self.rom_bank = value & 0x0F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The 512 bytes are echoed across 0xA000-0xBFFF, the upper nibble
+    /// isn't connected and reads as 1s
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        self.ram[address as usize & (MBC2_RAM_SIZE - 1)] | 0xF0
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        self.ram[address as usize & (MBC2_RAM_SIZE - 1)] = value & 0x0F;
+    }
+
+    /// Contents of the built-in RAM, one nibble per byte like other emulators
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_data(&mut self, data: &[u8]) {
+        for (nibble, byte) in self.ram.iter_mut().zip(data) {
+            *nibble = byte & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_rom;
+    use super::*;
+
+    fn make_mbc2() -> Mbc2 {
+        Mbc2::new(test_rom(16))
+    }
+
+    #[test]
+    fn test_register_select_by_address_bit_8() {
+        let mut mbc = make_mbc2();
+        // Bit 8 set selects the ROM bank
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        assert!(!mbc.ram_enabled);
+
+        // Bit 8 clear enables RAM, even in the upper half
+        mbc.write_rom(0x3000, 0x0A);
+        assert!(mbc.ram_enabled);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn test_half_byte_ram_echo() {
+        let mut mbc = make_mbc2();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA001, 0xAB);
+        assert_eq!(mbc.read_ram(0xA001), 0xFB);
+        assert_eq!(mbc.read_ram(0xA201), 0xFB);
+        assert_eq!(mbc.read_ram(0xBE01), 0xFB);
+    }
+}
@@ -0,0 +1,206 @@
+use super::rtc::{Rtc, RtcClock};
+use super::{banked_rom_read, Cartridge, CartridgeHeader, RAM_BANK_SIZE};
+
+/// MBC3, up to 2 MiB of ROM, 32 KiB of RAM and an optional real-time clock
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+
+    // 0x0000-0x1FFF, enables both RAM and the RTC registers
+    ram_enabled: bool,
+    // 0x2000-0x3FFF, 7-bit ROM bank for 0x4000-0x7FFF
+    rom_bank: u8,
+    // 0x4000-0x5FFF, 0x00-0x03 maps a RAM bank, 0x08-0x0C an RTC register
+    ram_select: u8,
+    // 0x6000-0x7FFF, writing 0x00 then 0x01 latches the clock
+    latch_armed: bool,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, header: Option<&CartridgeHeader>, clock: RtcClock) -> Self {
+        let ram_size = header.map_or(0, |h| h.ram_size());
+        let rtc = header
+            .is_some_and(|h| h.has_timer())
+            .then(|| Rtc::new(clock));
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch_armed: false,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let offset = self.ram_select as usize * RAM_BANK_SIZE + (address - 0xA000) as usize;
+        offset & (self.ram.len() - 1)
+    }
+}
+
+impl Cartridge for Mbc3 {
+    /// Advance the RTC when it runs from emulated time
+    fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        banked_rom_read(&self.rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // Unlike MBC1, only bank 0 itself is remapped to bank 1
+                self.rom_bank = value & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_select = value & 0x0F,
+            _ => {
+                if self.latch_armed && value == 0x01 {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch_armed = value == 0x00;
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_select, &self.rtc) {
+            (0x00..=0x03, _) if !self.ram.is_empty() => self.ram[self.ram_offset(address)],
+            (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_select),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match (self.ram_select, &mut self.rtc) {
+            (0x00..=0x03, _) if !self.ram.is_empty() => {
+                let offset = self.ram_offset(address);
+                self.ram[offset] = value;
+            }
+            (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_select, value),
+            _ => {}
+        }
+    }
+
+    /// Contents of the battery-backed RAM, followed by the 48-byte RTC
+    /// state when the cartridge has a clock
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend_from_slice(&rtc.to_bytes());
+        }
+        data
+    }
+
+    fn load_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+
+        // Saves without the RTC footer leave the clock as it is
+        if let (Some(rtc), Some(footer)) = (&mut self.rtc, data[len..].first_chunk()) {
+            *rtc = Rtc::from_bytes(rtc.clock(), footer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rtc::RTC_STATE_SIZE;
+    use super::super::test_rom;
+    use super::*;
+
+    fn make_mbc3(banks: usize) -> Mbc3 {
+        let mut mbc = Mbc3::new(test_rom(banks), None, RtcClock::Emulated);
+        mbc.ram = vec![0; 4 * RAM_BANK_SIZE];
+        mbc.rtc = Some(Rtc::new(RtcClock::Emulated));
+        mbc
+    }
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = make_mbc3(128);
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x20);
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut mbc = make_mbc3(4);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0xA123, 0x56);
+        assert_eq!(mbc.ram[3 * RAM_BANK_SIZE + 0x123], 0x56);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA123), 0x00);
+    }
+
+    #[test]
+    fn test_rtc_latch() {
+        let mut mbc = make_mbc3(4);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.tick(4_194_304 * 61);
+
+        // Registers only update on a 0x00, 0x01 write sequence
+        mbc.write_rom(0x4000, 0x09);
+        assert_eq!(mbc.read_ram(0xA000), 0);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 0);
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 1);
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 1);
+    }
+
+    #[test]
+    fn test_save_data_round_trip() {
+        let mut mbc = make_mbc3(4);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x99);
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_ram(0xA000, 5);
+
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 4 * RAM_BANK_SIZE + RTC_STATE_SIZE);
+
+        let mut loaded = make_mbc3(4);
+        loaded.load_data(&data);
+        assert_eq!(loaded.ram, mbc.ram);
+        // Everything but the timestamp at the end
+        let rtc_state = |mbc: &Mbc3| mbc.rtc.as_ref().unwrap().to_bytes()[..40].to_vec();
+        assert_eq!(rtc_state(&loaded), rtc_state(&mbc));
+    }
+}
@@ -0,0 +1,161 @@
+use super::{banked_rom_read, Cartridge, CartridgeHeader, RAM_BANK_SIZE};
+
+// On rumble carts bit 3 of the RAM bank register drives the motor
+const RUMBLE_BIT: u8 = 0x08;
+
+/// MBC5, up to 8 MiB of ROM and 128 KiB of RAM, optionally with a rumble motor
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rumble: bool,
+
+    // 0x0000-0x1FFF, 0x0A in the lower nibble enables RAM
+    ram_enabled: bool,
+    // 0x2000-0x2FFF lower 8 bits, 0x3000-0x3FFF bit 8, bank 0 can be selected
+    rom_bank: u16,
+    // 0x4000-0x5FFF, 4-bit RAM bank (3 bits on rumble carts)
+    ram_bank: u8,
+    motor_on: bool,
+    // Motor state change not yet seen by the front-end
+    rumble_event: Option<bool>,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, header: Option<&CartridgeHeader>) -> Self {
+        let ram_size = header.map_or(0, |h| h.ram_size());
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            rumble: header.is_some_and(|h| h.has_rumble()),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            motor_on: false,
+            rumble_event: None,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let offset = self.ram_bank as usize * RAM_BANK_SIZE + (address - 0xA000) as usize;
+        offset & (self.ram.len() - 1)
+    }
+}
+
+impl Cartridge for Mbc5 {
+    /// New motor state if the game switched it on or off since the last call
+    fn take_rumble_event(&mut self) -> Option<bool> {
+        self.rumble_event.take()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        banked_rom_read(&self.rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((value & 0x01) as u16) << 8
+            }
+            0x4000..=0x5FFF if self.rumble => {
+                self.ram_bank = value & 0x07;
+                let motor_on = value & RUMBLE_BIT != 0;
+                if motor_on != self.motor_on {
+                    self.motor_on = motor_on;
+                    self.rumble_event = Some(motor_on);
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(address);
+        self.ram[offset] = value;
+    }
+
+    /// Contents of the battery-backed RAM
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_rom;
+    use super::*;
+
+    fn make_mbc5(banks: usize) -> Mbc5 {
+        let mut mbc = Mbc5::new(test_rom(banks), None);
+        mbc.ram = vec![0; 16 * RAM_BANK_SIZE];
+        mbc
+    }
+
+    fn read_bank(mbc: &Mbc5) -> usize {
+        (mbc.read_rom(0x4001) as usize) << 8 | mbc.read_rom(0x4000) as usize
+    }
+
+    #[test]
+    fn test_9_bit_rom_banking() {
+        let mut mbc = make_mbc5(512);
+        mbc.write_rom(0x2000, 0x34);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(read_bank(&mbc), 0x134);
+
+        // Bank 0 can be mapped at 0x4000
+        mbc.write_rom(0x2000, 0x00);
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!(read_bank(&mbc), 0);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut mbc = make_mbc5(4);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0xBFFF, 0x42);
+        assert_eq!(mbc.ram[16 * RAM_BANK_SIZE - 1], 0x42);
+    }
+
+    #[test]
+    fn test_rumble_event() {
+        let mut mbc = make_mbc5(4);
+        mbc.rumble = true;
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x09);
+        assert_eq!(mbc.take_rumble_event(), Some(true));
+        assert_eq!(mbc.take_rumble_event(), None);
+
+        // The motor bit doesn't select a RAM bank
+        mbc.write_ram(0xA000, 0x11);
+        assert_eq!(mbc.ram[RAM_BANK_SIZE], 0x11);
+
+        mbc.write_rom(0x4000, 0x09);
+        assert_eq!(mbc.take_rumble_event(), None);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.take_rumble_event(), Some(false));
+    }
+}
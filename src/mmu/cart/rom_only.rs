@@ -0,0 +1,51 @@
+use super::{Cartridge, CartridgeHeader, RAM_BANK_SIZE};
+
+/// Cartridge without a memory bank controller, 32 KiB of ROM and
+/// optionally up to 8 KiB of RAM
+#[derive(Debug, PartialEq, Eq)]
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, header: Option<&CartridgeHeader>) -> Self {
+        let ram_size = header.map_or(0, |h| h.ram_size().min(RAM_BANK_SIZE));
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size],
+        }
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn read_rom(&self, address: u16) -> u8 {
+        self.rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {
+        // No registers to write to
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        let offset = (address - 0xA000) as usize;
+        self.ram.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        let offset = (address - 0xA000) as usize;
+        if let Some(byte) = self.ram.get_mut(offset) {
+            *byte = value;
+        }
+    }
+
+    /// Contents of the battery-backed RAM
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+}
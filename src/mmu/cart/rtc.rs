@@ -0,0 +1,294 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// CPU clock, the RTC counts one second every this many cycles when
+/// running from emulated time
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+/// Size of the RTC state appended to .sav files by most emulators
+pub const RTC_STATE_SIZE: usize = 48;
+
+// Day counter high register (0x0C)
+const DAY_HIGH_BIT: u8 = 0x01;
+const HALT_BIT: u8 = 0x40;
+const CARRY_BIT: u8 = 0x80;
+
+/// Where the RTC gets its time from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    // Counts emulated CPU cycles, so the clock follows the game speed
+    Emulated,
+    // Follows the host's wall clock, also across runs
+    Host,
+}
+
+/// Real-time clock of MBC3 cartridges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rtc {
+    clock: RtcClock,
+
+    // Live counters
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    // 9-bit day counter
+    days: u16,
+    halted: bool,
+    // Set when the day counter overflows, only cleared by the game
+    carry: bool,
+
+    // Copy of the registers taken on latch, this is what the game reads
+    latched: [u8; 5],
+
+    // Emulated cycles since the last second
+    cycles: u32,
+    // Unix time the counters were last brought up to date
+    timestamp: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Self {
+        Rtc {
+            clock,
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            carry: false,
+            latched: [0; 5],
+            cycles: 0,
+            timestamp: now(),
+        }
+    }
+
+    pub fn clock(&self) -> RtcClock {
+        self.clock
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        self.clock = clock;
+        self.timestamp = now();
+    }
+
+    /// Advance the clock by emulated CPU cycles
+    pub fn tick(&mut self, cycles: u32) {
+        if self.clock != RtcClock::Emulated || self.halted {
+            return;
+        }
+        self.cycles += cycles;
+        if self.cycles >= CYCLES_PER_SECOND {
+            self.advance((self.cycles / CYCLES_PER_SECOND) as u64);
+            self.cycles %= CYCLES_PER_SECOND;
+        }
+    }
+
+    /// Bring the counters up to date with the host clock
+    fn sync(&mut self) {
+        if self.clock != RtcClock::Host {
+            return;
+        }
+        let now = now();
+        if !self.halted {
+            self.advance(now.saturating_sub(self.timestamp));
+        }
+        self.timestamp = now;
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        if seconds == 0 {
+            return;
+        }
+        let total = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400
+            + seconds;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days >= 512 {
+            self.carry = true;
+        }
+        self.days = (days % 512) as u16;
+    }
+
+    fn day_high(&self) -> u8 {
+        (self.days >> 8) as u8 & DAY_HIGH_BIT
+            | if self.halted { HALT_BIT } else { 0 }
+            | if self.carry { CARRY_BIT } else { 0 }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            self.day_high(),
+        ]
+    }
+
+    /// Copy the live counters into the registers visible to the game
+    pub fn latch(&mut self) {
+        self.sync();
+        self.latched = self.registers();
+    }
+
+    /// Read a latched register, selected with 0x08-0x0C
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.latched[0] & 0x3F,
+            0x09 => self.latched[1] & 0x3F,
+            0x0A => self.latched[2] & 0x1F,
+            0x0B => self.latched[3],
+            0x0C => self.latched[4] & (DAY_HIGH_BIT | HALT_BIT | CARRY_BIT),
+            _ => 0xFF,
+        }
+    }
+
+    /// Write a live register, selected with 0x08-0x0C
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.sync();
+        match register {
+            0x08 => {
+                self.seconds = value & 0x3F;
+                // Writing the seconds resets the sub-second counter
+                self.cycles = 0;
+            }
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((value & DAY_HIGH_BIT) as u16) << 8;
+                self.halted = value & HALT_BIT != 0;
+                self.carry = value & CARRY_BIT != 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Serialise in the format shared by BGB, VBA-M, SameBoy and others:
+    /// live registers, latched registers (each as a little-endian u32),
+    /// then the unix timestamp as a little-endian u64
+    pub fn to_bytes(&self) -> [u8; RTC_STATE_SIZE] {
+        let mut state = self.clone();
+        state.sync();
+
+        let mut bytes = [0; RTC_STATE_SIZE];
+        let registers = state.registers().into_iter().chain(state.latched);
+        for (i, register) in registers.enumerate() {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&(register as u32).to_le_bytes());
+        }
+        bytes[40..].copy_from_slice(&now().to_le_bytes());
+        bytes
+    }
+
+    /// Restore from the 48-byte format. On the host clock the time that
+    /// passed since the state was saved is caught up on.
+    pub fn from_bytes(clock: RtcClock, bytes: &[u8; RTC_STATE_SIZE]) -> Self {
+        let register = |i: usize| bytes[i * 4];
+        let mut rtc = Rtc::new(clock);
+        rtc.seconds = register(0) & 0x3F;
+        rtc.minutes = register(1) & 0x3F;
+        rtc.hours = register(2) & 0x1F;
+        rtc.days = register(3) as u16 | ((register(4) & DAY_HIGH_BIT) as u16) << 8;
+        rtc.halted = register(4) & HALT_BIT != 0;
+        rtc.carry = register(4) & CARRY_BIT != 0;
+        for (i, latched) in rtc.latched.iter_mut().enumerate() {
+            *latched = register(5 + i);
+        }
+
+        if clock == RtcClock::Host {
+            let mut timestamp = [0; 8];
+            timestamp.copy_from_slice(&bytes[40..]);
+            rtc.timestamp = u64::from_le_bytes(timestamp).min(rtc.timestamp);
+            rtc.sync();
+        }
+        rtc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emulated_clock() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.write(0x0A, 23);
+        rtc.write(0x09, 59);
+        rtc.write(0x08, 59);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+
+        rtc.tick(CYCLES_PER_SECOND - 1);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 59);
+
+        // Day 511 rolls over to 0 and sets the carry bit
+        rtc.tick(1);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 0);
+        assert_eq!(rtc.read(0x09), 0);
+        assert_eq!(rtc.read(0x0A), 0);
+        assert_eq!(rtc.read(0x0B), 0);
+        assert_eq!(rtc.read(0x0C), CARRY_BIT);
+    }
+
+    #[test]
+    fn test_halt_stops_clock() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.write(0x0C, HALT_BIT);
+        rtc.tick(CYCLES_PER_SECOND * 5);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 0);
+        assert_eq!(rtc.read(0x0C), HALT_BIT);
+    }
+
+    #[test]
+    fn test_reads_are_latched() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.tick(CYCLES_PER_SECOND * 3);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 3);
+    }
+
+    #[test]
+    fn test_serialisation_round_trip() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.write(0x0B, 0x34);
+        rtc.write(0x0C, DAY_HIGH_BIT | HALT_BIT);
+        rtc.write(0x0A, 12);
+        rtc.latch();
+
+        let bytes = rtc.to_bytes();
+        assert_eq!(bytes[8], 12);
+        assert_eq!(bytes[16], DAY_HIGH_BIT | HALT_BIT);
+
+        let restored = Rtc::from_bytes(RtcClock::Emulated, &bytes);
+        assert_eq!(restored.registers(), rtc.registers());
+        assert_eq!(restored.latched, rtc.latched);
+    }
+
+    #[test]
+    fn test_host_clock_catches_up() {
+        let mut bytes = Rtc::new(RtcClock::Host).to_bytes();
+        // Pretend the save is 90 seconds old
+        let timestamp = now() - 90;
+        bytes[40..].copy_from_slice(&timestamp.to_le_bytes());
+
+        let mut rtc = Rtc::from_bytes(RtcClock::Host, &bytes);
+        rtc.latch();
+        assert_eq!(rtc.read(0x09), 1);
+        assert!(rtc.read(0x08) >= 30);
+    }
+}
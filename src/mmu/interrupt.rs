@@ -0,0 +1,121 @@
+/// Interrupt sources, in priority order (VBlank is serviced first)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Bit of the interrupt in IF and IE
+    pub fn bit(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0x01,
+            Interrupt::LcdStat => 0x02,
+            Interrupt::Timer => 0x04,
+            Interrupt::Serial => 0x08,
+            Interrupt::Joypad => 0x10,
+        }
+    }
+
+    /// Address the CPU jumps to when servicing the interrupt
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LcdStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InterruptController {
+    // Interrupt Flag (0xFF0F), only the lower 5 bits are used
+    flag: u8,
+    // Interrupt Enable (0xFFFF)
+    enable: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        InterruptController { flag: 0, enable: 0 }
+    }
+
+    /// Read IF, the unused upper bits always read as 1
+    pub fn read_flag(&self) -> u8 {
+        self.flag | 0xE0
+    }
+
+    pub fn write_flag(&mut self, value: u8) {
+        self.flag = value & 0x1F;
+    }
+
+    pub fn read_enable(&self) -> u8 {
+        self.enable
+    }
+
+    pub fn write_enable(&mut self, value: u8) {
+        self.enable = value;
+    }
+
+    /// Set the IF bit for an interrupt
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flag |= interrupt.bit();
+    }
+
+    /// Set several IF bits at once, as collected from a peripheral
+    pub fn request_bits(&mut self, bits: u8) {
+        self.flag |= bits & 0x1F;
+    }
+
+    /// Clear the IF bit of an interrupt that is being serviced
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flag &= !interrupt.bit();
+    }
+
+    /// Highest priority interrupt that is both requested and enabled
+    pub fn pending(&self) -> Option<Interrupt> {
+        let active = self.flag & self.enable;
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| active & interrupt.bit() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority() {
+        let mut ic = InterruptController::new();
+        ic.write_enable(0x1F);
+        ic.request(Interrupt::Joypad);
+        ic.request(Interrupt::Timer);
+        assert_eq!(ic.pending(), Some(Interrupt::Timer));
+
+        ic.acknowledge(Interrupt::Timer);
+        assert_eq!(ic.pending(), Some(Interrupt::Joypad));
+        assert_eq!(ic.read_flag(), 0xF0);
+    }
+
+    #[test]
+    fn test_disabled_interrupt_not_pending() {
+        let mut ic = InterruptController::new();
+        ic.write_enable(Interrupt::VBlank.bit());
+        ic.request(Interrupt::Serial);
+        assert_eq!(ic.pending(), None);
+    }
+}
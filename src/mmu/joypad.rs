@@ -0,0 +1,179 @@
+use super::Interrupt;
+
+// P1 select lines, a group is selected when its bit is 0
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_ACTIONS: u8 = 0x20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    /// Bit in the pressed state, directions in the lower nibble and
+    /// actions in the upper one, in P1 line order
+    fn bit(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+/// Joypad register P1 (0xFF00)
+#[derive(Debug, PartialEq, Eq)]
+pub struct Joypad {
+    // Bits 4-5 as last written by the game
+    select: u8,
+    // One bit per held button, 1 means pressed
+    pressed: u8,
+    interrupt_requests: u8,
+    // A line went low since the last `take_wake`, ends STOP even when the
+    // joypad interrupt is disabled
+    wake: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            select: SELECT_DIRECTIONS | SELECT_ACTIONS,
+            pressed: 0,
+            interrupt_requests: 0,
+            wake: false,
+        }
+    }
+
+    /// Input lines P10-P13, active low
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            pressed |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            pressed |= self.pressed >> 4;
+        }
+        !pressed & 0x0F
+    }
+
+    /// Apply a change and raise the joypad interrupt if a line went low
+    fn update(&mut self, change: impl FnOnce(&mut Self)) {
+        let old_lines = self.lines();
+        change(self);
+        if old_lines & !self.lines() != 0 {
+            self.interrupt_requests |= Interrupt::Joypad.bit();
+            self.wake = true;
+        }
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.update(|joypad| joypad.pressed |= button.bit());
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.update(|joypad| joypad.pressed &= !button.bit());
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    /// Only the select lines are writable
+    pub fn write(&mut self, value: u8) {
+        self.update(|joypad| joypad.select = value & (SELECT_DIRECTIONS | SELECT_ACTIONS));
+    }
+
+    /// Whether a line went low since the last call
+    pub fn take_wake(&mut self) -> bool {
+        std::mem::take(&mut self.wake)
+    }
+
+    /// Hand the pending interrupt requests over to the interrupt controller
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupt_requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_groups() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Start);
+        joypad.press(Button::Left);
+
+        // Nothing selected reads all lines high
+        assert_eq!(joypad.read(), 0xFF);
+
+        joypad.write(SELECT_ACTIONS);
+        assert_eq!(joypad.read(), 0xED);
+        joypad.write(SELECT_DIRECTIONS);
+        assert_eq!(joypad.read(), 0xD7);
+
+        // Both groups are ANDed together
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC5);
+
+        joypad.release(Button::Start);
+        assert_eq!(joypad.read(), 0xCD);
+    }
+
+    #[test]
+    fn test_interrupt_on_high_to_low() {
+        let mut joypad = Joypad::new();
+
+        // Unselected buttons don't pull any line low
+        joypad.press(Button::A);
+        assert_eq!(joypad.take_interrupts(), 0);
+
+        // Selecting the group with A held does
+        joypad.write(SELECT_DIRECTIONS);
+        assert_eq!(joypad.take_interrupts(), Interrupt::Joypad.bit());
+
+        joypad.release(Button::A);
+        assert_eq!(joypad.take_interrupts(), 0);
+        joypad.press(Button::B);
+        assert_eq!(joypad.take_interrupts(), Interrupt::Joypad.bit());
+    }
+
+    #[test]
+    fn test_wake_on_high_to_low() {
+        let mut joypad = Joypad::new();
+        joypad.write(SELECT_DIRECTIONS);
+        assert!(!joypad.take_wake());
+
+        joypad.press(Button::Start);
+        // Taking the interrupt doesn't consume the wake-up
+        joypad.take_interrupts();
+        assert!(joypad.take_wake());
+        assert!(!joypad.take_wake());
+
+        joypad.release(Button::Start);
+        assert!(!joypad.take_wake());
+    }
+}
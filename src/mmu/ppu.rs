@@ -1,18 +1,23 @@
-use sdl3::pixels::Color;
-use sdl3::rect::Point;
-use sdl3::render::WindowCanvas;
+use super::Interrupt;
+
+mod fifo;
+
+use fifo::Fifo;
 
 // Constants
 pub const SCREEN_WIDTH: u32 = 160;
 pub const SCREEN_HEIGHT: u32 = 144;
 pub const TILE_SIZE: u32 = 8;
+pub const FRAMEBUFFER_SIZE: usize = (SCREEN_WIDTH * SCREEN_HEIGHT) as usize;
+// Tiles in 0x8000-0x97FF
+const TILE_COUNT: usize = 384;
 
-// Game Boy has 4 shades of "color"
-pub const GAMEBOY_COLORS: [sdl3::pixels::Color; 4] = [
-    Color::RGB(255, 255, 255), // White
-    Color::RGB(192, 192, 192), // Light gray
-    Color::RGB(96, 96, 96),    // Dark gray
-    Color::RGB(0, 0, 0),       // Black
+// Game Boy has 4 shades of "color", as RGBA bytes
+pub const GAMEBOY_COLORS: [[u8; 4]; 4] = [
+    [255, 255, 255, 255], // White
+    [192, 192, 192, 255], // Light gray
+    [96, 96, 96, 255],    // Dark gray
+    [0, 0, 0, 255],       // Black
 ];
 
 // PPU Mode constants
@@ -29,14 +34,24 @@ const SCANLINE_CYCLES: u32 = OAM_SCAN_CYCLES + DRAWING_CYCLES + HBLANK_CYCLES;
 const VBLANK_CYCLES: u32 = SCANLINE_CYCLES * 10;
 const FRAME_CYCLES: u32 = SCANLINE_CYCLES * 154;
 
-#[derive(Debug, PartialEq, Eq)]
+/// How mode 3 turns VRAM into pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    // Draws each line in one go at the end of a fixed-length mode 3
+    Scanline,
+    // Runs the pixel FIFO dot by dot, so registers written during mode 3
+    // take effect mid-line and mode 3 gets its real length
+    Fifo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Tile {
     pub data: [[u8; 8]; 8],
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct PPU {
-    // Tile data (0x8000-0x97FF)
+    // Tile data (0x8000-0x97FF), decoded from VRAM as it's written
     pub tiles: Vec<Tile>,
 
     // Background tile maps (0x9800-0x9FFF)
@@ -47,6 +62,12 @@ pub struct PPU {
     mode: u8,
     scan_line: u8,
     cycle_counter: u32,
+    // Length of the current HBlank, shorter when mode 3 ran long
+    hblank_cycles: u32,
+
+    renderer: Renderer,
+    // Pixel FIFO state, used when the FIFO renderer draws the line
+    fifo: Fifo,
 
     // LCD Control Register (0xFF40)
     lcd_enabled: bool,
@@ -64,6 +85,11 @@ pub struct PPU {
     vblank_interrupt: bool,
     hblank_interrupt: bool,
     lyc_equal: bool,
+    // All enabled STAT conditions ORed together, the interrupt fires when
+    // this goes from low to high
+    stat_line: bool,
+    // The DMG STAT write glitch doesn't happen on the CGB
+    cgb_mode: bool,
 
     // Position and scrolling registers
     scroll_y: u8,   // 0xFF42
@@ -72,34 +98,52 @@ pub struct PPU {
     window_y: u8,   // 0xFF4A
     window_x: u8,   // 0xFF4B
 
+    // Window state for the current frame
+    // Set once LY has matched WY, the window can only show from then on
+    window_y_triggered: bool,
+    // Internal line counter, only advances on lines the window was drawn
+    window_line: u8,
+    // WX=166 makes the window cover the whole next line
+    window_spill: bool,
+
     // Palette data
     bg_palette: u8,   // 0xFF47
     obj_palette0: u8, // 0xFF48
     obj_palette1: u8, // 0xFF49
 
-    // Framebuffer
-    framebuffer: [[u8; SCREEN_WIDTH as usize]; SCREEN_HEIGHT as usize],
+    // Framebuffer, one shade (0-3) per pixel, row by row
+    framebuffer: [u8; FRAMEBUFFER_SIZE],
 
     // VRAM
     pub vram: [u8; 0x2000],
 
     // OAM (Sprite Attribute Table)
-    oam: [u8; 0xA0],
+    pub oam: [u8; 0xA0],
+
+    // BG and window color indices of the line being rendered, before the
+    // palette is applied, sprites need them for BG-over-OBJ priority
+    bg_line: [u8; SCREEN_WIDTH as usize],
 
     // Flag to indicate if a frame is ready to be rendered
     frame_ready: bool,
+
+    // Interrupts raised since the MMU last collected them (IF bit layout)
+    interrupt_requests: u8,
     // Optional canvas for rendering (for testing purposes)
 }
 
 impl PPU {
     pub fn new() -> Self {
         PPU {
-            tiles: Vec::with_capacity(384),
+            tiles: vec![Tile { data: [[0; 8]; 8] }; TILE_COUNT],
             bg_tilemap: [[0; 32]; 32],
             window_tilemap: [[0; 32]; 32],
             mode: MODE_OAM_SCAN,
             scan_line: 0,
             cycle_counter: 0,
+            hblank_cycles: HBLANK_CYCLES,
+            renderer: Renderer::Scanline,
+            fifo: Fifo::new(),
             lcd_enabled: false,
             window_tile_map: false,
             window_enabled: false,
@@ -113,31 +157,51 @@ impl PPU {
             vblank_interrupt: false,
             hblank_interrupt: false,
             lyc_equal: false,
+            stat_line: false,
+            cgb_mode: false,
             scroll_y: 0,
             scroll_x: 0,
             ly_compare: 0,
             window_y: 0,
             window_x: 0,
+            window_y_triggered: false,
+            window_line: 0,
+            window_spill: false,
             bg_palette: 0xE4, // Default Game Boy palette
             obj_palette0: 0,
             obj_palette1: 0,
-            framebuffer: [[0; SCREEN_WIDTH as usize]; SCREEN_HEIGHT as usize],
+            framebuffer: [0; FRAMEBUFFER_SIZE],
             vram: [0; 0x2000],
             oam: [0; 0xA0],
+            bg_line: [0; SCREEN_WIDTH as usize],
             frame_ready: false,
+            interrupt_requests: 0,
         }
     }
 
-    /// Initialize PPU with ROM data
-    pub fn init(&mut self, rom_bank0: &[u8], _rom_bank1: &[u8]) {}
-
-    pub fn push_tile(&mut self, t: Tile) {
-        self.tiles.push(t);
+    /// Run as a CGB PPU rather than a DMG one
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
     }
 
     /// Update LCD Control Register (0xFF40)
     pub fn update_lcd_control(&mut self, value: u8) {
-        self.lcd_enabled = (value & 0x80) != 0;
+        let lcd_enabled = (value & 0x80) != 0;
+        let was_enabled = std::mem::replace(&mut self.lcd_enabled, lcd_enabled);
+        if was_enabled && !lcd_enabled {
+            // Turning the LCD off resets LY and leaves the PPU in HBlank,
+            // with the STAT line low
+            self.scan_line = 0;
+            self.cycle_counter = 0;
+            self.mode = MODE_HBLANK;
+            self.hblank_cycles = HBLANK_CYCLES;
+            self.fifo = Fifo::new();
+            self.reset_window();
+            self.update_stat_line();
+        } else if !was_enabled && lcd_enabled {
+            self.mode = MODE_OAM_SCAN;
+            self.compare_ly();
+        }
         self.window_tile_map = (value & 0x40) != 0;
         self.window_enabled = (value & 0x20) != 0;
         self.bg_window_tile_data = (value & 0x10) != 0;
@@ -149,11 +213,36 @@ impl PPU {
 
     /// Update LCD Status Register (0xFF41)
     pub fn update_lcd_status(&mut self, value: u8) {
+        // On the DMG the write enables every condition for a cycle first, so
+        // it raises a spurious interrupt in HBlank, VBlank or when LY=LYC
+        if !self.cgb_mode {
+            self.lyc_interrupt = true;
+            self.vblank_interrupt = true;
+            self.hblank_interrupt = true;
+            self.update_stat_line();
+        }
+
         self.lyc_interrupt = (value & 0x40) != 0;
         self.oam_interrupt = (value & 0x20) != 0;
         self.vblank_interrupt = (value & 0x10) != 0;
         self.hblank_interrupt = (value & 0x08) != 0;
         // Mode and LYC equal bits are read-only
+        self.update_stat_line();
+    }
+
+    /// Read LCD Status Register (0xFF41)
+    pub fn read_lcd_status(&self) -> u8 {
+        0x80 | (self.lyc_interrupt as u8) << 6
+            | (self.oam_interrupt as u8) << 5
+            | (self.vblank_interrupt as u8) << 4
+            | (self.hblank_interrupt as u8) << 3
+            | (self.lyc_equal as u8) << 2
+            | self.mode
+    }
+
+    /// Current scanline, LY (0xFF44)
+    pub fn get_ly(&self) -> u8 {
+        self.scan_line
     }
 
     // Setter methods for PPU registers
@@ -165,6 +254,9 @@ impl PPU {
     }
     pub fn set_ly_compare(&mut self, value: u8) {
         self.ly_compare = value;
+        if self.lcd_enabled {
+            self.compare_ly();
+        }
     }
     pub fn set_window_y(&mut self, value: u8) {
         self.window_y = value;
@@ -183,31 +275,169 @@ impl PPU {
         self.obj_palette1 = value;
     }
 
+    /// Pick the renderer, it takes over from the next line
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
     /// Get the color from a palette based on the color index
     fn get_color_from_palette(&self, palette: u8, color_idx: u8) -> u8 {
         let shift = color_idx * 2;
         (palette >> shift) & 0x03
     }
 
-    /// Update a tile when VRAM is written to
+    /// Raise a VBlank or STAT interrupt
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_requests |= interrupt.bit();
+    }
+
+    /// Hand the pending interrupt requests over to the interrupt controller
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupt_requests)
+    }
+
+    /// Write tile data (0x8000-0x97FF) and decode the tile row it changed
     pub fn update_tile(&mut self, address: u16, value: u8) {
-        todo!();
+        let offset = (address - 0x8000) as usize;
+        if self.vram[offset] == value {
+            return;
+        }
+        self.vram[offset] = value;
+
+        // Rows are two bytes, the low bits of each pixel then the high bits
+        let row_address = offset & !1;
+        self.tiles[offset / 16].data[offset % 16 / 2] =
+            get_pixelrow(self.vram[row_address], self.vram[row_address + 1]);
     }
 
     /// Update the PPU state for the given number of cycles
     /// Returns true if a frame is ready to be rendered
     pub fn update(&mut self, cycles: u32) -> bool {
-        false
+        if !self.lcd_enabled {
+            return false;
+        }
+
+        let mut frame_started = false;
+        self.cycle_counter += cycles;
+        loop {
+            match self.mode {
+                MODE_OAM_SCAN if self.cycle_counter >= OAM_SCAN_CYCLES => {
+                    self.cycle_counter -= OAM_SCAN_CYCLES;
+                    if self.renderer == Renderer::Fifo {
+                        self.fifo_start_line();
+                    }
+                    self.set_mode(MODE_DRAWING);
+                }
+                // The FIFO renderer decides when mode 3 ends, one dot at a time
+                MODE_DRAWING if self.fifo.is_active() && self.cycle_counter > 0 => {
+                    self.cycle_counter -= 1;
+                    if self.fifo_dot() {
+                        self.hblank_cycles = self.fifo_end_line();
+                        self.set_mode(MODE_HBLANK);
+                    }
+                }
+                MODE_DRAWING if !self.fifo.is_active() && self.cycle_counter >= DRAWING_CYCLES => {
+                    self.cycle_counter -= DRAWING_CYCLES;
+                    self.render_scanline();
+                    self.hblank_cycles = HBLANK_CYCLES;
+                    self.set_mode(MODE_HBLANK);
+                }
+                MODE_HBLANK if self.cycle_counter >= self.hblank_cycles => {
+                    self.cycle_counter -= self.hblank_cycles;
+                    self.scan_line += 1;
+                    self.compare_ly();
+                    if self.scan_line == SCREEN_HEIGHT as u8 {
+                        self.set_mode(MODE_VBLANK);
+                        self.request_interrupt(Interrupt::VBlank);
+                        self.frame_ready = true;
+                        frame_started = true;
+                    } else {
+                        self.set_mode(MODE_OAM_SCAN);
+                    }
+                }
+                // VBlank is 10 lines of SCANLINE_CYCLES each
+                MODE_VBLANK if self.cycle_counter >= SCANLINE_CYCLES => {
+                    self.cycle_counter -= SCANLINE_CYCLES;
+                    self.scan_line += 1;
+                    let vblank_lines = VBLANK_CYCLES / SCANLINE_CYCLES;
+                    if self.scan_line as u32 == SCREEN_HEIGHT + vblank_lines {
+                        self.scan_line = 0;
+                        self.frame_ready = false;
+                        self.reset_window();
+                        self.set_mode(MODE_OAM_SCAN);
+                    }
+                    self.compare_ly();
+                }
+                _ => break,
+            }
+        }
+        frame_started
+    }
+
+    /// Switch mode and update the STAT line for it
+    fn set_mode(&mut self, mode: u8) {
+        self.mode = mode;
+        self.update_stat_line();
+    }
+
+    /// Update the coincidence flag after LY or LYC changed
+    fn compare_ly(&mut self) {
+        self.lyc_equal = self.scan_line == self.ly_compare;
+        self.update_stat_line();
+    }
+
+    /// Recompute the STAT line and raise the interrupt on a rising edge, so
+    /// a condition that starts while another holds the line high is missed
+    fn update_stat_line(&mut self) {
+        let mode_condition = match self.mode {
+            MODE_HBLANK => self.hblank_interrupt,
+            MODE_VBLANK => self.vblank_interrupt,
+            MODE_OAM_SCAN => self.oam_interrupt,
+            _ => false,
+        };
+        let stat_line =
+            self.lcd_enabled && (mode_condition || (self.lyc_equal && self.lyc_interrupt));
+        if stat_line && !self.stat_line {
+            self.request_interrupt(Interrupt::LcdStat);
+        }
+        self.stat_line = stat_line;
+    }
+
+    /// The CPU can't reach VRAM while mode 3 reads it
+    pub fn vram_accessible(&self) -> bool {
+        !self.lcd_enabled || self.mode != MODE_DRAWING
+    }
+
+    /// The CPU can't reach OAM during the OAM scan and mode 3
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcd_enabled || self.mode == MODE_HBLANK || self.mode == MODE_VBLANK
+    }
+
+    /// Write a shade to the framebuffer on the current line
+    fn set_pixel(&mut self, x: usize, shade: u8) {
+        self.framebuffer[self.scan_line as usize * SCREEN_WIDTH as usize + x] = shade;
     }
 
     /// Render a single scanline to the framebuffer
     fn render_scanline(&mut self) {
+        // With BG and window off the line shows color 0
+        self.bg_line = [0; SCREEN_WIDTH as usize];
+        let start = self.scan_line as usize * SCREEN_WIDTH as usize;
+        self.framebuffer[start..start + SCREEN_WIDTH as usize].fill(0);
+
         if self.bg_window_priority {
             self.render_background_scanline();
         }
 
+        // WY is compared at the start of every line, whether or not the
+        // window is enabled
+        if self.scan_line == self.window_y {
+            self.window_y_triggered = true;
+        }
         if self.window_enabled {
             self.render_window_scanline();
+        } else {
+            self.window_spill = false;
         }
 
         if self.sprites_enabled {
@@ -215,24 +445,195 @@ impl PPU {
         }
     }
 
+    /// Offset in VRAM of a BG or window tile, following the addressing
+    /// mode selected by LCDC bit 4
+    fn bg_tile_address(&self, tile_index: u8) -> usize {
+        if self.bg_window_tile_data {
+            // 0x8000-0x8FFF, unsigned index
+            tile_index as usize * 16
+        } else {
+            // 0x8800-0x97FF, signed index around 0x9000
+            (0x1000 + (tile_index as i8 as i32) * 16) as usize
+        }
+    }
+
+    /// Color index (0-3) of a pixel in the tile at a VRAM offset
+    fn tile_pixel(&self, tile_address: usize, row: u8, column: u8) -> u8 {
+        self.tiles[tile_address / 16].data[row as usize][column as usize]
+    }
+
+    /// Color index of a pixel from a 32x32 tile map at a VRAM offset
+    fn tile_map_pixel(&self, map_address: usize, x: u8, y: u8) -> u8 {
+        let tile_index = self.vram[map_address + (y as usize / 8) * 32 + x as usize / 8];
+        self.tile_pixel(self.bg_tile_address(tile_index), y % 8, x % 8)
+    }
+
     /// Render the background layer for the current scanline
     fn render_background_scanline(&mut self) {
-        todo!()
+        let map_address = if self.bg_tile_map { 0x1C00 } else { 0x1800 };
+        let y = self.scan_line.wrapping_add(self.scroll_y);
+        for x in 0..SCREEN_WIDTH as u8 {
+            let color = self.tile_map_pixel(map_address, x.wrapping_add(self.scroll_x), y);
+            self.bg_line[x as usize] = color;
+            self.set_pixel(
+                x as usize,
+                self.get_color_from_palette(self.bg_palette, color),
+            );
+        }
+    }
+
+    /// Start a new frame with the window line counter at 0
+    fn reset_window(&mut self) {
+        self.window_y_triggered = false;
+        self.window_line = 0;
+        self.window_spill = false;
     }
 
     /// Render the window layer for the current scanline
     fn render_window_scanline(&mut self) {
-        todo!()
+        let spill = std::mem::take(&mut self.window_spill);
+        // WX is offset by 7, 7 puts the window at the left edge and
+        // anything past 166 hides it
+        if !self.window_y_triggered || (self.window_x > SCREEN_WIDTH as u8 + 6 && !spill) {
+            return;
+        }
+
+        // WX=166 only shows the last column, but the window then covers
+        // the whole next line
+        if self.window_x == SCREEN_WIDTH as u8 + 6 {
+            self.window_spill = true;
+        }
+
+        // On the DMG bit 0 of LCDC blanks the window as well, but it still
+        // uses up a window line
+        if self.bg_window_priority {
+            let map_address = if self.window_tile_map { 0x1C00 } else { 0x1800 };
+            // WX=0-6 starts the window at the left edge with its first
+            // 7-WX pixels cut off
+            let start = if spill {
+                0
+            } else {
+                self.window_x.saturating_sub(7)
+            };
+            for x in start..SCREEN_WIDTH as u8 {
+                let window_x = if spill { x } else { x + 7 - self.window_x };
+                let color = self.tile_map_pixel(map_address, window_x, self.window_line);
+                self.bg_line[x as usize] = color;
+                self.set_pixel(
+                    x as usize,
+                    self.get_color_from_palette(self.bg_palette, color),
+                );
+            }
+        }
+        self.window_line += 1;
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.sprite_size {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// OAM scan, the first 10 sprites on the current line in OAM order
+    fn scan_oam(&self) -> Vec<[u8; 4]> {
+        let height = self.sprite_height() as i16;
+        let line = self.scan_line as i16;
+        self.oam
+            .chunks_exact(4)
+            .filter(|sprite| {
+                let top = sprite[0] as i16 - 16;
+                line >= top && line < top + height
+            })
+            .take(10)
+            .map(|sprite| [sprite[0], sprite[1], sprite[2], sprite[3]])
+            .collect()
+    }
+
+    /// Color indices of a sprite's 8 pixels on the current line, left to
+    /// right with flipping applied
+    fn sprite_pixels(&self, sprite: [u8; 4]) -> [u8; 8] {
+        let (y, tile, attributes) = (sprite[0], sprite[2], sprite[3]);
+        let height = self.sprite_height();
+        let y_flip = attributes & 0x40 != 0;
+        let x_flip = attributes & 0x20 != 0;
+
+        let mut row = self.scan_line.wrapping_sub(y.wrapping_sub(16));
+        if y_flip {
+            row = height - 1 - row;
+        }
+        // 8x16 sprites ignore bit 0 of the tile index
+        let tile = if height == 16 { tile & 0xFE } else { tile };
+        // Sprites always use 0x8000 addressing, the second tile of an
+        // 8x16 sprite directly follows the first
+        let tile_address = tile as usize * 16 + (row as usize / 8) * 16;
+
+        let mut pixels = [0; 8];
+        for (column, pixel) in pixels.iter_mut().enumerate() {
+            let column = column as u8;
+            let tile_column = if x_flip { 7 - column } else { column };
+            *pixel = self.tile_pixel(tile_address, row % 8, tile_column);
+        }
+        pixels
+    }
+
+    /// Palette a sprite selects with attribute bit 4
+    fn sprite_palette(&self, attributes: u8) -> u8 {
+        if attributes & 0x10 != 0 {
+            self.obj_palette1
+        } else {
+            self.obj_palette0
+        }
     }
 
     /// Render sprites for the current scanline
     fn render_sprites_scanline(&mut self) {
-        todo!()
+        let mut sprites = self.scan_oam();
+        // Lower X wins, then lower OAM index, which the stable sort keeps
+        sprites.sort_by_key(|sprite| sprite[1]);
+
+        // Winning sprite pixel per column: color index, palette, behind BG
+        let mut pixels: [Option<(u8, u8, bool)>; SCREEN_WIDTH as usize] =
+            [None; SCREEN_WIDTH as usize];
+        for sprite in sprites {
+            let (x, attributes) = (sprite[1], sprite[3]);
+            let behind_bg = attributes & 0x80 != 0;
+            let palette = self.sprite_palette(attributes);
+
+            for (column, color) in self.sprite_pixels(sprite).into_iter().enumerate() {
+                let screen_x = x as i16 - 8 + column as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&screen_x) {
+                    continue;
+                }
+                let pixel = &mut pixels[screen_x as usize];
+                // Color 0 is transparent, lower priority sprites show through
+                if pixel.is_none() && color != 0 {
+                    *pixel = Some((color, palette, behind_bg));
+                }
+            }
+        }
+
+        for (x, pixel) in pixels.into_iter().enumerate() {
+            if let Some((color, palette, behind_bg)) = pixel {
+                if behind_bg && self.bg_line[x] != 0 {
+                    continue;
+                }
+                self.set_pixel(x, self.get_color_from_palette(palette, color));
+            }
+        }
     }
 
-    /// Render the framebuffer to the provided canvas
-    pub fn render(&mut self, canvas: &mut WindowCanvas) {
-        todo!()
+    /// The last frame drawn, one shade per pixel from 0 (lightest) to 3
+    pub fn framebuffer(&self) -> &[u8; FRAMEBUFFER_SIZE] {
+        &self.framebuffer
+    }
+
+    /// Convert the frame to RGBA8888, 4 bytes per pixel in R, G, B, A order
+    pub fn framebuffer_rgba(&self, rgba: &mut [u8; FRAMEBUFFER_SIZE * 4]) {
+        for (pixel, &shade) in rgba.chunks_exact_mut(4).zip(&self.framebuffer) {
+            pixel.copy_from_slice(&GAMEBOY_COLORS[shade as usize]);
+        }
     }
 
     /// Check if a frame is ready to be rendered
@@ -251,23 +652,6 @@ impl PPU {
     }
 }
 
-/// Render a single tile to the canvas
-pub fn render_tile(tile: [[u8; 8]; 8], canvas: &mut WindowCanvas, position: Point) {
-    // Draw the 8x8 tile
-    for y in 0..8u32 {
-        for x in 0..8u32 {
-            let ci = tile[y as usize][x as usize];
-            let c_col = GAMEBOY_COLORS[ci as usize];
-            canvas.set_draw_color(c_col);
-            let p = Point::new(
-                x as i32, // Center the tile
-                y as i32,
-            ) + position;
-            canvas.draw_point(p).expect("Failed to draw point");
-        }
-    }
-}
-
 /// Convert two bytes into a row of 8 pixels (2 bits per pixel)
 pub fn get_pixelrow(b1: u8, b2: u8) -> [u8; 8] {
     let mut c1: [u8; 8] = [0; 8];
@@ -311,6 +695,366 @@ pub fn load_tileset(rom: &[u8]) -> Vec<Tile> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_mode_timing() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x80);
+        assert_eq!(ppu.read_lcd_status() & 0x03, MODE_OAM_SCAN);
+
+        ppu.update(OAM_SCAN_CYCLES);
+        assert_eq!(ppu.read_lcd_status() & 0x03, MODE_DRAWING);
+        ppu.update(DRAWING_CYCLES);
+        assert_eq!(ppu.read_lcd_status() & 0x03, MODE_HBLANK);
+        ppu.update(HBLANK_CYCLES - 4);
+        assert_eq!(ppu.get_ly(), 0);
+        ppu.update(4);
+        assert_eq!(ppu.get_ly(), 1);
+        assert_eq!(ppu.read_lcd_status() & 0x03, MODE_OAM_SCAN);
+    }
+
+    #[test]
+    fn test_vblank() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x80);
+        assert!(!ppu.update(SCANLINE_CYCLES * 144 - 4));
+        assert!(ppu.update(4));
+        assert!(ppu.is_frame_ready());
+        assert_eq!(ppu.get_ly(), 144);
+        assert_eq!(ppu.read_lcd_status() & 0x03, MODE_VBLANK);
+        assert_eq!(ppu.take_interrupts(), Interrupt::VBlank.bit());
+
+        // The frame wraps back to line 0 after line 153
+        ppu.update(SCANLINE_CYCLES * 10);
+        assert_eq!(ppu.get_ly(), 0);
+        assert_eq!(ppu.read_lcd_status() & 0x03, MODE_OAM_SCAN);
+        assert!(!ppu.is_frame_ready());
+    }
+
+    #[test]
+    fn test_stat_interrupts() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x80);
+        ppu.set_ly_compare(2);
+        ppu.update_lcd_status(0x48);
+
+        ppu.update(OAM_SCAN_CYCLES + DRAWING_CYCLES);
+        assert_eq!(ppu.take_interrupts(), Interrupt::LcdStat.bit());
+        ppu.update(HBLANK_CYCLES);
+        assert_eq!(ppu.take_interrupts(), 0);
+
+        // LY=LYC starts as HBlank ends, the line stays high and there's no
+        // new edge
+        ppu.update(SCANLINE_CYCLES - HBLANK_CYCLES);
+        ppu.take_interrupts();
+        ppu.update(HBLANK_CYCLES);
+        assert_eq!(ppu.get_ly(), 2);
+        assert_eq!(ppu.read_lcd_status() & 0x04, 0x04);
+        assert_eq!(ppu.take_interrupts(), 0);
+
+        // The line only drops once neither condition holds
+        ppu.update(SCANLINE_CYCLES + OAM_SCAN_CYCLES + DRAWING_CYCLES);
+        assert_eq!(ppu.take_interrupts(), Interrupt::LcdStat.bit());
+    }
+
+    #[test]
+    fn test_stat_line_edges() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x80);
+        ppu.set_ly_compare(5);
+        ppu.update_lcd_status(0x40);
+        assert_eq!(ppu.take_interrupts(), 0);
+
+        // Writing LYC to the current line raises the line
+        ppu.set_ly_compare(0);
+        assert_eq!(ppu.take_interrupts(), Interrupt::LcdStat.bit());
+        ppu.set_ly_compare(0);
+        assert_eq!(ppu.take_interrupts(), 0);
+
+        // On the DMG a STAT write in HBlank fires a spurious interrupt
+        ppu.set_ly_compare(1);
+        ppu.update(OAM_SCAN_CYCLES + DRAWING_CYCLES);
+        ppu.take_interrupts();
+        ppu.update_lcd_status(0x00);
+        assert_eq!(ppu.take_interrupts(), Interrupt::LcdStat.bit());
+
+        ppu.set_cgb_mode(true);
+        ppu.update_lcd_status(0x00);
+        assert_eq!(ppu.take_interrupts(), 0);
+
+        // Nothing fires with the LCD off
+        ppu.update_lcd_control(0x00);
+        ppu.set_cgb_mode(false);
+        ppu.update_lcd_status(0x78);
+        assert_eq!(ppu.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn test_vram_oam_access() {
+        let mut ppu = PPU::new();
+        assert!(ppu.vram_accessible() && ppu.oam_accessible());
+
+        ppu.update_lcd_control(0x80);
+        assert!(ppu.vram_accessible());
+        assert!(!ppu.oam_accessible());
+        ppu.update(OAM_SCAN_CYCLES);
+        assert!(!ppu.vram_accessible());
+        assert!(!ppu.oam_accessible());
+        ppu.update(DRAWING_CYCLES);
+        assert!(ppu.vram_accessible() && ppu.oam_accessible());
+    }
+
+    #[test]
+    fn test_lcd_off_resets_ly() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x80);
+        ppu.update(SCANLINE_CYCLES * 5 + 10);
+        ppu.update_lcd_control(0x00);
+        assert_eq!(ppu.get_ly(), 0);
+        assert_eq!(ppu.read_lcd_status() & 0x03, MODE_HBLANK);
+        assert!(!ppu.update(FRAME_CYCLES));
+    }
+
+    #[test]
+    fn test_framebuffer_rgba() {
+        let mut ppu = PPU::new();
+        ppu.framebuffer[1] = 3;
+        ppu.framebuffer[FRAMEBUFFER_SIZE - 1] = 1;
+
+        let mut rgba = [0; FRAMEBUFFER_SIZE * 4];
+        ppu.framebuffer_rgba(&mut rgba);
+        assert_eq!(rgba[..8], [255, 255, 255, 255, 0, 0, 0, 255]);
+        assert_eq!(rgba[rgba.len() - 4..], GAMEBOY_COLORS[1]);
+    }
+
+    #[test]
+    fn test_update_tile() {
+        let mut ppu = PPU::new();
+        assert_eq!(ppu.tiles.len(), TILE_COUNT);
+
+        // Row 3 of tile 2, then the last row of the last tile
+        ppu.update_tile(0x8026, 0b1010_0000);
+        ppu.update_tile(0x8027, 0b1100_0000);
+        assert_eq!(ppu.tiles[2].data[3], [3, 2, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(ppu.vram[0x26..0x28], [0b1010_0000, 0b1100_0000]);
+        ppu.update_tile(0x97FF, 0x01);
+        assert_eq!(ppu.tiles[383].data[7], [0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(ppu.tiles[2].data[2], [0; 8]);
+    }
+
+    /// Fill a tile in VRAM with one color index
+    fn fill_tile(ppu: &mut PPU, address: usize, color: u8) {
+        for row in 0..8 {
+            let address = 0x8000 + (address + row * 2) as u16;
+            ppu.update_tile(address, if color & 1 != 0 { 0xFF } else { 0 });
+            ppu.update_tile(address + 1, if color & 2 != 0 { 0xFF } else { 0 });
+        }
+    }
+
+    fn set_sprite(ppu: &mut PPU, index: usize, y: u8, x: u8, tile: u8, attributes: u8) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[y, x, tile, attributes]);
+    }
+
+    fn line(ppu: &PPU) -> [u8; SCREEN_WIDTH as usize] {
+        let start = ppu.scan_line as usize * SCREEN_WIDTH as usize;
+        ppu.framebuffer[start..start + SCREEN_WIDTH as usize]
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_background_addressing_and_scroll() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x91);
+        fill_tile(&mut ppu, 0x10, 1);
+        ppu.vram[0x1801] = 1;
+        ppu.set_scroll_x(4);
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[3], 0);
+        assert_eq!(line(&ppu)[4], 1);
+        assert_eq!(line(&ppu)[11], 1);
+        assert_eq!(line(&ppu)[12], 0);
+
+        // Signed addressing puts tile 0xFF just below 0x9000
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x81);
+        fill_tile(&mut ppu, 0x0FF0, 3);
+        ppu.vram[0x1800] = 0xFF;
+        ppu.set_bg_palette(0x1B);
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[0], 0);
+        assert_eq!(line(&ppu)[8], 3);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0xF1);
+        fill_tile(&mut ppu, 0x10, 2);
+        ppu.vram[0x1C00] = 1;
+        ppu.set_window_x(87);
+        ppu.set_window_y(1);
+
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[80], 0);
+
+        ppu.scan_line = 1;
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[79], 0);
+        assert_eq!(line(&ppu)[80], 2);
+        assert_eq!(line(&ppu)[87], 2);
+        assert_eq!(line(&ppu)[88], 0);
+    }
+
+    /// Render lines with the window turned off on some of them
+    fn render_lines(ppu: &mut PPU, lines: u8, window_off: &[u8]) {
+        for line in 0..lines {
+            ppu.scan_line = line;
+            let lcdc = if window_off.contains(&line) {
+                0xD1
+            } else {
+                0xF1
+            };
+            ppu.update_lcd_control(lcdc);
+            ppu.render_scanline();
+        }
+    }
+
+    #[test]
+    fn test_window_line_counter() {
+        let mut ppu = PPU::new();
+        // Window map row 0 is tile 1, row 1 is tile 2
+        fill_tile(&mut ppu, 0x10, 1);
+        fill_tile(&mut ppu, 0x20, 2);
+        ppu.vram[0x1C00] = 1;
+        ppu.vram[0x1C20] = 2;
+        ppu.set_window_x(7);
+        ppu.set_window_y(2);
+
+        // Lines where the window is hidden don't advance the counter, so
+        // line 14 draws window line 8 rather than 12
+        render_lines(&mut ppu, 15, &[4, 5, 6, 7]);
+        assert_eq!(ppu.window_line, 9);
+        assert_eq!(line(&ppu)[0], 2);
+
+        // Moving WY below LY later in the frame doesn't hide the window
+        let mut ppu = PPU::new();
+        ppu.set_window_x(7);
+        ppu.set_window_y(0);
+        render_lines(&mut ppu, 3, &[]);
+        ppu.set_window_y(100);
+        render_lines(&mut ppu, 1, &[]);
+        assert_eq!(ppu.window_line, 4);
+    }
+
+    #[test]
+    fn test_window_x_edge_cases() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0xF1);
+        // Only the first column of window tile 0 is colored
+        ppu.update_tile(0x8010, 0x80);
+        ppu.update_tile(0x8012, 0x80);
+        ppu.vram[0x1C00] = 1;
+
+        // WX below 7 cuts off the left of the window
+        ppu.set_window_x(6);
+        ppu.render_scanline();
+        assert_eq!(line(&ppu), [0; SCREEN_WIDTH as usize]);
+        ppu.set_window_x(7);
+        ppu.reset_window();
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[0], 1);
+
+        // WX=166 draws one column, then the whole next line
+        ppu.reset_window();
+        ppu.set_window_x(166);
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[159], 1);
+        ppu.scan_line = 1;
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[0], 1);
+        assert_eq!(ppu.window_line, 2);
+
+        // Past 166 the window is hidden and the counter stops, once the
+        // spill from the last WX=166 line is drawn
+        ppu.set_window_x(167);
+        ppu.scan_line = 2;
+        ppu.render_scanline();
+        ppu.scan_line = 3;
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[0], 0);
+        assert_eq!(ppu.window_line, 3);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x83);
+        ppu.set_obj_palette0(0xE4);
+        fill_tile(&mut ppu, 0x10, 1);
+        fill_tile(&mut ppu, 0x20, 3);
+
+        // Lower X wins even with a higher OAM index
+        set_sprite(&mut ppu, 0, 16, 20, 2, 0);
+        set_sprite(&mut ppu, 1, 16, 16, 1, 0);
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[7], 0);
+        assert_eq!(line(&ppu)[8], 1);
+        assert_eq!(line(&ppu)[15], 1);
+        assert_eq!(line(&ppu)[16], 3);
+        assert_eq!(line(&ppu)[19], 3);
+    }
+
+    #[test]
+    fn test_ten_sprites_per_line() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x83);
+        ppu.set_obj_palette0(0xE4);
+        fill_tile(&mut ppu, 0x10, 1);
+        for i in 0..11 {
+            set_sprite(&mut ppu, i, 16, 8 + i as u8 * 8, 1, 0);
+        }
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[79], 1);
+        assert_eq!(line(&ppu)[80], 0);
+    }
+
+    #[test]
+    fn test_tall_sprites_and_flips() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x87);
+        ppu.set_obj_palette0(0xE4);
+        fill_tile(&mut ppu, 0x40, 1);
+        fill_tile(&mut ppu, 0x50, 3);
+        // Only the leftmost column of tile 6
+        ppu.update_tile(0x8060, 0x80);
+
+        // Bit 0 of the tile index is ignored, the top half is tile 4
+        set_sprite(&mut ppu, 0, 16, 8, 5, 0);
+        set_sprite(&mut ppu, 1, 16, 16, 5, 0x40);
+        set_sprite(&mut ppu, 2, 16, 24, 6, 0x20);
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[0], 1);
+        assert_eq!(line(&ppu)[8], 3);
+        assert_eq!(line(&ppu)[16], 0);
+        assert_eq!(line(&ppu)[23], 1);
+    }
+
+    #[test]
+    fn test_bg_over_obj() {
+        let mut ppu = PPU::new();
+        ppu.update_lcd_control(0x93);
+        ppu.set_obj_palette0(0xE4);
+        fill_tile(&mut ppu, 0x10, 2);
+        fill_tile(&mut ppu, 0x20, 3);
+        ppu.vram[0x1800] = 1;
+
+        // Behind BG colors 1-3, above BG color 0
+        set_sprite(&mut ppu, 0, 16, 12, 2, 0x80);
+        ppu.render_scanline();
+        assert_eq!(line(&ppu)[4], 2);
+        assert_eq!(line(&ppu)[8], 3);
+    }
+
     #[test]
     fn test_pixel() {
         assert_eq!(get_pixelrow(0x7c, 0x7c), [0, 3, 3, 3, 3, 3, 0, 0]);
@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+
+use super::{PPU, SCREEN_WIDTH};
+
+// Dots lost at the start of every line to a tile fetch that's thrown away
+const DISCARDED_FETCH_DOTS: u8 = 6;
+// Dots the fetcher is stalled for while it reads a sprite's tile
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// Steps of the background fetcher, all but Push take two dots
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    // Waits until the BG FIFO is empty, then fills it in a single dot
+    Push,
+}
+
+/// Sprite pixel waiting in the OBJ FIFO
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct ObjPixel {
+    // 0 is transparent
+    color: u8,
+    attributes: u8,
+}
+
+const TRANSPARENT: ObjPixel = ObjPixel {
+    color: 0,
+    attributes: 0,
+};
+
+/// Pixel FIFO renderer state for the line in mode 3
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fifo {
+    active: bool,
+    // Color indices from the BG or window fetcher
+    bg: VecDeque<u8>,
+    obj: VecDeque<ObjPixel>,
+
+    // Background fetcher
+    step: FetchStep,
+    // Each step takes two dots, the work happens on the second
+    step_started: bool,
+    // Tile column fetched next, relative to SCX or the window's left edge
+    fetch_x: u8,
+    tile_index: u8,
+    low: u8,
+    high: u8,
+    fetching_window: bool,
+    // The window started on this line, whether or not it's visible
+    window_drawn: bool,
+    // The previous line had WX=166, the window covers this whole line
+    window_spill: bool,
+
+    // Screen column of the next pixel out of the FIFO
+    x: u8,
+    // Pixels thrown away at the start of the line for SCX fine scroll, or
+    // the window's hidden left edge when WX < 7
+    discard: u8,
+    // Dots where nothing moves, for the discarded first fetch and sprite
+    // fetches
+    stall: u8,
+    // Sprites found by the OAM scan that the fetcher hasn't reached yet
+    sprites: Vec<[u8; 4]>,
+    // Length of mode 3 so far
+    dots: u32,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Fifo {
+            active: false,
+            bg: VecDeque::with_capacity(8),
+            obj: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_started: false,
+            fetch_x: 0,
+            tile_index: 0,
+            low: 0,
+            high: 0,
+            fetching_window: false,
+            window_drawn: false,
+            window_spill: false,
+            x: 0,
+            discard: 0,
+            stall: 0,
+            sprites: Vec::new(),
+            dots: 0,
+        }
+    }
+
+    /// Whether the FIFO is drawing the current line
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Restart the fetcher on a new tile row, the BG or window
+    fn restart_fetcher(&mut self) {
+        self.bg.clear();
+        self.step = FetchStep::Tile;
+        self.step_started = false;
+        self.fetch_x = 0;
+    }
+}
+
+impl PPU {
+    /// Set up the FIFO at the start of mode 3
+    pub(super) fn fifo_start_line(&mut self) {
+        if self.scan_line == self.window_y {
+            self.window_y_triggered = true;
+        }
+
+        self.fifo = Fifo {
+            active: true,
+            window_spill: std::mem::take(&mut self.window_spill),
+            discard: self.scroll_x % 8,
+            stall: DISCARDED_FETCH_DOTS,
+            sprites: self.scan_oam(),
+            ..Fifo::new()
+        };
+    }
+
+    /// Finish the line and return how long HBlank lasts
+    pub(super) fn fifo_end_line(&mut self) -> u32 {
+        self.fifo.active = false;
+        if self.fifo.window_drawn {
+            self.window_line += 1;
+        }
+        super::SCANLINE_CYCLES - super::OAM_SCAN_CYCLES - self.fifo.dots
+    }
+
+    /// Run the FIFO for one dot, returns true once the last pixel of the
+    /// line is out
+    pub(super) fn fifo_dot(&mut self) -> bool {
+        self.fifo.dots += 1;
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return false;
+        }
+
+        self.check_window();
+
+        // A sprite at the current column holds back the pixel output
+        if let Some(index) = self.next_sprite() {
+            if self.fifo.bg.is_empty() {
+                self.fetcher_dot();
+                if self.fifo.bg.is_empty() {
+                    return false;
+                }
+            }
+            // The fetcher finishes its current tile before reading the
+            // sprite, which takes up to 5 dots, less the more of the tile
+            // has already been shifted out
+            let shifted = 8 - self.fifo.bg.len() as u8;
+            let sprite = self.fifo.sprites.remove(index);
+            self.fetch_sprite(sprite);
+            self.fifo.stall = SPRITE_FETCH_DOTS + 5u8.saturating_sub(shifted) - 1;
+            return false;
+        }
+
+        self.fetcher_dot();
+        self.shift_pixel();
+        self.fifo.x == SCREEN_WIDTH as u8
+    }
+
+    /// Switch the fetcher over to the window once it's reached WX
+    fn check_window(&mut self) {
+        let fifo = &self.fifo;
+        if fifo.fetching_window || !self.window_enabled || !self.window_y_triggered {
+            return;
+        }
+        if !fifo.window_spill && fifo.x + 7 < self.window_x {
+            return;
+        }
+
+        // WX=0-6 cuts off the window's first 7-WX pixels. WX=166 shows a
+        // single column, then the window covers the whole next line.
+        let window_x = if self.fifo.window_spill {
+            7
+        } else {
+            self.window_x
+        };
+        if window_x == SCREEN_WIDTH as u8 + 6 {
+            self.window_spill = true;
+        }
+        self.fifo.discard = 7u8.saturating_sub(window_x);
+        self.fifo.restart_fetcher();
+        self.fifo.fetching_window = true;
+        self.fifo.window_drawn = true;
+    }
+
+    /// Index of a sprite starting at the current column
+    fn next_sprite(&self) -> Option<usize> {
+        if !self.sprites_enabled {
+            return None;
+        }
+        // Sprites partly off the left edge all start at column 0
+        let x = self.fifo.x + 8;
+        self.fifo.sprites.iter().position(|sprite| sprite[1] <= x)
+    }
+
+    /// Mix a sprite into the OBJ FIFO, pixels already there from an earlier
+    /// sprite win unless they're transparent
+    fn fetch_sprite(&mut self, sprite: [u8; 4]) {
+        let (x, attributes) = (sprite[1], sprite[3]);
+        // Columns off the left edge of the screen are dropped
+        let hidden = (self.fifo.x + 8).saturating_sub(x) as usize;
+        let pixels = self.sprite_pixels(sprite);
+
+        let obj = &mut self.fifo.obj;
+        if obj.len() < 8 - hidden {
+            obj.resize(8 - hidden, TRANSPARENT);
+        }
+        for (slot, &color) in obj.iter_mut().zip(&pixels[hidden..]) {
+            if slot.color == 0 && color != 0 {
+                *slot = ObjPixel { color, attributes };
+            }
+        }
+    }
+
+    /// Advance the BG/window fetcher by one dot
+    fn fetcher_dot(&mut self) {
+        if self.fifo.step == FetchStep::Push {
+            if self.fifo.bg.is_empty() {
+                for column in 0..8 {
+                    let bit = 7 - column;
+                    let color = ((self.fifo.high >> bit) & 1) << 1 | ((self.fifo.low >> bit) & 1);
+                    self.fifo.bg.push_back(color);
+                }
+                self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+                self.fifo.step = FetchStep::Tile;
+            }
+            return;
+        }
+
+        if !self.fifo.step_started {
+            self.fifo.step_started = true;
+            return;
+        }
+        self.fifo.step_started = false;
+
+        // Registers are read as each step happens, so writes during mode 3
+        // show up partway through the line
+        let (map_address, tile_x, y) = if self.fifo.fetching_window {
+            let map_address = if self.window_tile_map { 0x1C00 } else { 0x1800 };
+            (map_address, self.fifo.fetch_x, self.window_line)
+        } else {
+            let map_address = if self.bg_tile_map { 0x1C00 } else { 0x1800 };
+            let tile_x = (self.scroll_x / 8).wrapping_add(self.fifo.fetch_x);
+            (
+                map_address,
+                tile_x,
+                self.scan_line.wrapping_add(self.scroll_y),
+            )
+        };
+        let row_address = self.bg_tile_address(self.fifo.tile_index) + (y % 8) as usize * 2;
+
+        match self.fifo.step {
+            FetchStep::Tile => {
+                let offset = (y as usize / 8) * 32 + (tile_x % 32) as usize;
+                self.fifo.tile_index = self.vram[map_address + offset];
+                self.fifo.step = FetchStep::DataLow;
+            }
+            FetchStep::DataLow => {
+                self.fifo.low = self.vram[row_address];
+                self.fifo.step = FetchStep::DataHigh;
+            }
+            FetchStep::DataHigh => {
+                self.fifo.high = self.vram[row_address + 1];
+                self.fifo.step = FetchStep::Push;
+            }
+            FetchStep::Push => unreachable!(),
+        }
+    }
+
+    /// Shift one pixel out of the FIFOs onto the screen
+    fn shift_pixel(&mut self) {
+        let Some(mut color) = self.fifo.bg.pop_front() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let obj = self.fifo.obj.pop_front().unwrap_or(TRANSPARENT);
+
+        // On the DMG bit 0 of LCDC blanks the BG and window
+        if !self.bg_window_priority {
+            color = 0;
+        }
+        let mut shade = self.get_color_from_palette(self.bg_palette, color);
+        let behind_bg = obj.attributes & 0x80 != 0;
+        if obj.color != 0 && self.sprites_enabled && !(behind_bg && color != 0) {
+            shade = self.get_color_from_palette(self.sprite_palette(obj.attributes), obj.color);
+        }
+
+        let x = self.fifo.x as usize;
+        self.bg_line[x] = color;
+        self.set_pixel(x, shade);
+        self.fifo.x += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{DRAWING_CYCLES, MODE_DRAWING, OAM_SCAN_CYCLES};
+    use super::*;
+
+    fn make_ppu(lcdc: u8) -> PPU {
+        let mut ppu = PPU::new();
+        ppu.set_renderer(super::super::Renderer::Fifo);
+        ppu.update_lcd_control(lcdc);
+        ppu
+    }
+
+    /// Run through mode 3 of the current line and return its length
+    fn draw_line(ppu: &mut PPU) -> u32 {
+        ppu.update(OAM_SCAN_CYCLES);
+        let mut dots = 0;
+        while ppu.read_lcd_status() & 0x03 == MODE_DRAWING {
+            ppu.update(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn test_mode3_length() {
+        let mut ppu = make_ppu(0x93);
+        assert_eq!(draw_line(&mut ppu), DRAWING_CYCLES);
+
+        // SCX fine scroll throws pixels away at the start of the line
+        let mut ppu = make_ppu(0x93);
+        ppu.set_scroll_x(3);
+        assert_eq!(draw_line(&mut ppu), DRAWING_CYCLES + 3);
+
+        // The window restarts the fetcher
+        let mut ppu = make_ppu(0xB3);
+        ppu.set_window_x(87);
+        assert_eq!(draw_line(&mut ppu), DRAWING_CYCLES + 6);
+
+        // A sprite stalls the fetcher, less so when the fetcher is further
+        // into its tile
+        let mut ppu = make_ppu(0x93);
+        ppu.oam[..4].copy_from_slice(&[16, 8 + 80, 0, 0]);
+        assert_eq!(draw_line(&mut ppu), DRAWING_CYCLES + 11);
+        let mut ppu = make_ppu(0x93);
+        ppu.oam[..4].copy_from_slice(&[16, 8 + 83, 0, 0]);
+        assert_eq!(draw_line(&mut ppu), DRAWING_CYCLES + 8);
+
+        // HBlank takes up the rest of the line
+        let mut ppu = make_ppu(0x93);
+        ppu.set_scroll_x(5);
+        draw_line(&mut ppu);
+        assert_eq!(ppu.hblank_cycles, super::super::HBLANK_CYCLES - 5);
+    }
+
+    #[test]
+    fn test_matches_scanline_renderer() {
+        let mut scanline = PPU::new();
+        let mut fifo = make_ppu(0xF3);
+        for ppu in [&mut scanline, &mut fifo] {
+            ppu.update_lcd_control(0xF3);
+            ppu.set_obj_palette0(0xE4);
+            ppu.set_obj_palette1(0x1B);
+            for i in 0..0x400 {
+                ppu.update_tile(0x8000 + i, (i * 37 % 256) as u8);
+            }
+            for (i, byte) in ppu.vram[0x1800..].iter_mut().enumerate() {
+                *byte = (i % 64) as u8;
+            }
+            ppu.set_scroll_x(13);
+            ppu.set_scroll_y(5);
+            ppu.set_window_x(100);
+            ppu.set_window_y(2);
+            ppu.oam[..12].copy_from_slice(&[16, 4, 3, 0x00, 18, 50, 5, 0x90, 20, 54, 7, 0x20]);
+        }
+
+        for _ in 0..8 {
+            draw_line(&mut fifo);
+            fifo.update(fifo.hblank_cycles);
+            scanline.update(super::super::SCANLINE_CYCLES);
+        }
+        let lines = 8 * SCREEN_WIDTH as usize;
+        assert_eq!(fifo.framebuffer[..lines], scanline.framebuffer[..lines]);
+    }
+
+    #[test]
+    fn test_mid_line_palette_write() {
+        let mut ppu = make_ppu(0x91);
+        // Tile 0 is color 1 everywhere
+        for row in 0..8 {
+            ppu.update_tile(0x8000 + row * 2, 0xFF);
+        }
+
+        // Change BGP about halfway through the line
+        ppu.update(OAM_SCAN_CYCLES + 90);
+        ppu.set_bg_palette(0xE8);
+        draw_line(&mut ppu);
+
+        let line = &ppu.framebuffer[..SCREEN_WIDTH as usize];
+        assert_eq!(line[0], 1);
+        assert_eq!(line[159], 2);
+        let split = line.iter().position(|&shade| shade == 2).unwrap();
+        assert!((70..90).contains(&split));
+        assert!(line[split..].iter().all(|&shade| shade == 2));
+    }
+}
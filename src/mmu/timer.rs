@@ -0,0 +1,216 @@
+use super::Interrupt;
+
+// Internal counter value left by the DMG boot ROM, DIV reads 0xAB
+const POST_BOOT_COUNTER: u16 = 0xABCC;
+
+/// DIV, TIMA, TMA and TAC (0xFF04-0xFF07)
+#[derive(Debug, PartialEq, Eq)]
+pub struct Timer {
+    // 16-bit counter incremented every T-cycle, DIV is its upper byte
+    counter: u16,
+    // Timer counter (0xFF05)
+    tima: u8,
+    // Timer modulo (0xFF06), loaded into TIMA when it overflows
+    tma: u8,
+    // Timer control (0xFF07), bit 2 enables TIMA, bits 0-1 pick the frequency
+    tac: u8,
+
+    // TIMA overflowed and reads 0, it's reloaded on the next M-cycle
+    overflow_pending: bool,
+    // TIMA was reloaded from TMA during the current M-cycle
+    reloading: bool,
+    // T-cycles left over from the last tick, less than an M-cycle
+    leftover: u32,
+    interrupt_requests: u8,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: POST_BOOT_COUNTER,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow_pending: false,
+            reloading: false,
+            leftover: 0,
+            interrupt_requests: 0,
+        }
+    }
+
+    /// Counter bit whose falling edge increments TIMA
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0x03 {
+            0b00 => 1 << 9, // 4096 Hz
+            0b01 => 1 << 3, // 262144 Hz
+            0b10 => 1 << 5, // 65536 Hz
+            _ => 1 << 7,    // 16384 Hz
+        }
+    }
+
+    /// Input of the falling edge detector, the selected bit ANDed with
+    /// the enable bit
+    fn signal(&self) -> bool {
+        self.tac & 0x04 != 0 && self.counter & self.selected_bit() != 0
+    }
+
+    /// Increment TIMA if the signal went from high to low
+    fn detect_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            if overflow {
+                self.overflow_pending = true;
+            }
+        }
+    }
+
+    /// Advance the timer by CPU T-cycles
+    pub fn tick(&mut self, cycles: u32) {
+        self.leftover += cycles;
+        while self.leftover >= 4 {
+            self.leftover -= 4;
+            self.step();
+        }
+    }
+
+    /// Advance by one M-cycle
+    fn step(&mut self) {
+        // TIMA reads 0 for one M-cycle after overflowing before TMA is loaded
+        self.reloading = self.overflow_pending;
+        if self.overflow_pending {
+            self.overflow_pending = false;
+            self.tima = self.tma;
+            self.interrupt_requests |= Interrupt::Timer.bit();
+        }
+
+        let old_signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_edge(old_signal);
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            // Any write clears the whole counter, which can tick TIMA
+            0xFF04 => {
+                let old_signal = self.signal();
+                self.counter = 0;
+                self.detect_edge(old_signal);
+            }
+            // Writes in the reload cycle lose to TMA, writes before it
+            // cancel the reload and the interrupt
+            0xFF05 if !self.reloading => {
+                self.tima = value;
+                self.overflow_pending = false;
+            }
+            0xFF06 => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            }
+            0xFF07 => {
+                // Disabling the timer or switching frequency can also
+                // produce a falling edge
+                let old_signal = self.signal();
+                self.tac = value & 0x07;
+                self.detect_edge(old_signal);
+            }
+            _ => {}
+        }
+    }
+
+    /// Hand the pending interrupt requests over to the interrupt controller
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupt_requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_timer(tac: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write(0xFF04, 0);
+        timer.write(0xFF07, tac);
+        timer
+    }
+
+    #[test]
+    fn test_div() {
+        let mut timer = Timer::new();
+        assert_eq!(timer.read(0xFF04), 0xAB);
+        timer.write(0xFF04, 0x12);
+        assert_eq!(timer.read(0xFF04), 0);
+        timer.tick(256);
+        assert_eq!(timer.read(0xFF04), 1);
+    }
+
+    #[test]
+    fn test_tima_frequency() {
+        // 262144 Hz, every 16 T-cycles
+        let mut timer = make_timer(0x05);
+        timer.tick(15 * 4);
+        assert_eq!(timer.read(0xFF05), 3);
+
+        // Disabled timers don't count
+        let mut timer = make_timer(0x01);
+        timer.tick(64);
+        assert_eq!(timer.read(0xFF05), 0);
+    }
+
+    #[test]
+    fn test_overflow_reload_delay() {
+        let mut timer = make_timer(0x05);
+        timer.write(0xFF06, 0x42);
+        timer.write(0xFF05, 0xFF);
+        timer.tick(16);
+
+        // TIMA is 0 for one M-cycle before TMA is loaded
+        assert_eq!(timer.read(0xFF05), 0);
+        assert_eq!(timer.take_interrupts(), 0);
+        timer.tick(4);
+        assert_eq!(timer.read(0xFF05), 0x42);
+        assert_eq!(timer.take_interrupts(), Interrupt::Timer.bit());
+    }
+
+    #[test]
+    fn test_tima_write_cancels_reload() {
+        let mut timer = make_timer(0x05);
+        timer.write(0xFF05, 0xFF);
+        timer.tick(16);
+        timer.write(0xFF05, 0x10);
+        timer.tick(4);
+        assert_eq!(timer.read(0xFF05), 0x10);
+        assert_eq!(timer.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn test_div_write_falling_edge() {
+        let mut timer = make_timer(0x05);
+        // Bit 3 of the counter is set, resetting it increments TIMA
+        timer.tick(8);
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+    }
+
+    #[test]
+    fn test_tac_disable_falling_edge() {
+        let mut timer = make_timer(0x05);
+        timer.tick(8);
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 1);
+        assert_eq!(timer.read(0xFF07), 0xF9);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::mmu::MMU;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often save RAM is flushed to disk while the game runs
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Battery-backed cartridge RAM persisted to a .sav file
+pub struct SaveFile {
+    path: PathBuf,
+    // What is on disk, to skip writes when nothing changed
+    last_saved: Vec<u8>,
+    last_save_time: Instant,
+}
+
+impl SaveFile {
+    /// The save sits in `save_dir`, or next to the ROM when it's None.
+    /// Returns None for cartridges without a battery.
+    pub fn for_rom(rom_path: &Path, save_dir: Option<&Path>, mmu: &MMU) -> Option<Self> {
+        if !mmu.has_battery() {
+            return None;
+        }
+
+        let path = save_path(rom_path, save_dir)?;
+
+        Some(SaveFile {
+            path,
+            last_saved: Vec::new(),
+            last_save_time: Instant::now(),
+        })
+    }
+
+    /// Load the save into the cartridge, a missing file is a fresh game
+    pub fn load(&mut self, mmu: &mut MMU) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                log::info!("Loaded save {}", self.path.display());
+                mmu.load_data(&data);
+                self.last_saved = data;
+                Ok(())
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Write the save if the cartridge RAM changed since the last write
+    pub fn save(&mut self, mmu: &MMU) -> io::Result<()> {
+        self.last_save_time = Instant::now();
+        let data = mmu.save_data();
+        if data == self.last_saved {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        // Write to a temporary file first so a crash can't leave a torn save
+        let tmp = self.path.with_extension("sav.tmp");
+        fs::write(&tmp, &data)?;
+        fs::rename(&tmp, &self.path)?;
+
+        log::debug!("Wrote save {}", self.path.display());
+        self.last_saved = data;
+        Ok(())
+    }
+
+    /// Save when the periodic interval has elapsed
+    pub fn save_periodically(&mut self, mmu: &MMU) -> io::Result<()> {
+        if self.last_save_time.elapsed() < SAVE_INTERVAL {
+            return Ok(());
+        }
+        self.save(mmu)
+    }
+}
+
+/// The ROM path with a .sav extension, moved into `save_dir` if given
+fn save_path(rom_path: &Path, save_dir: Option<&Path>) -> Option<PathBuf> {
+    let file_name = rom_path.with_extension("sav");
+    match save_dir {
+        Some(dir) => Some(dir.join(file_name.file_name()?)),
+        None => Some(file_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32 KiB ROM with the given cartridge type and 8 KiB of RAM
+    fn make_rom(cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = cartridge_type;
+        rom[0x149] = 0x02;
+        rom
+    }
+
+    #[test]
+    fn test_save_path() {
+        let rom = Path::new("roms/game.gb");
+        assert_eq!(save_path(rom, None), Some(PathBuf::from("roms/game.sav")));
+        assert_eq!(
+            save_path(rom, Some(Path::new("saves"))),
+            Some(PathBuf::from("saves/game.sav"))
+        );
+    }
+
+    #[test]
+    fn test_no_battery() {
+        // MBC1+RAM, nothing survives a power cycle
+        let mmu = MMU::new(make_rom(0x02));
+        assert!(SaveFile::for_rom(Path::new("game.gb"), None, &mmu).is_none());
+    }
+
+    #[test]
+    fn test_mbc3_save_size() {
+        // MBC3+TIMER+RAM+BATTERY saves the 48-byte RTC footer after the RAM
+        let mmu = MMU::new(make_rom(0x10));
+        assert_eq!(mmu.save_data().len(), 0x2000 + 48);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = std::env::temp_dir().join(format!("puro_boy_save_{}", std::process::id()));
+        let rom_path = Path::new("roms/game.gb");
+
+        // MBC3+RAM+BATTERY, with RAM enabled and a byte written to it
+        let mut mmu = MMU::new(make_rom(0x13));
+        mmu.write(0x0000, 0x0A);
+        mmu.write(0xA000, 0x42);
+        let mut save = SaveFile::for_rom(rom_path, Some(&dir), &mmu).unwrap();
+        assert_eq!(save.path, dir.join("game.sav"));
+        save.save(&mmu).unwrap();
+        assert!(save.path.exists());
+
+        let mut mmu = MMU::new(make_rom(0x13));
+        mmu.write(0x0000, 0x0A);
+        let mut save = SaveFile::for_rom(rom_path, Some(&dir), &mmu).unwrap();
+        save.load(&mut mmu).unwrap();
+        assert_eq!(mmu.read(0xA000), 0x42);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}